@@ -0,0 +1,35 @@
+//! The loader's hardware-independent modules, built for the host so that their unit tests run with
+//! `cargo test -p host-tests` (or `make test`). The kernel crate itself only builds for the
+//! Raspberry Pi.
+//!
+//! The modules are the kernel's own source files, included by path. Only their tests use them.
+
+#![allow(dead_code)]
+// The kernel's nightly predates `is_multiple_of` and `Option::is_none_or`.
+#![allow(clippy::manual_is_multiple_of, clippy::unnecessary_map_or)]
+
+#[path = "../../src/bsp/device_driver/common.rs"]
+mod common;
+
+#[path = "../../src/bsp/device_driver/bcm/bcm2xxx_p1011_uart/divisor.rs"]
+mod pl011_divisor;
+
+/// Stands in for the kernel's `loader` module. The submodules are found next to it.
+#[path = "../../src/loader"]
+mod loader {
+    /// The variants of `loader::Error` the modules below return.
+    pub enum Error {
+        Decompress(&'static str),
+        Elf(&'static str),
+        Segment { index: usize, start: usize, end: usize },
+        Fdt(&'static str),
+        Manifest(&'static str),
+        Region { index: usize, start: usize, end: usize },
+    }
+
+    pub mod crc32;
+    pub mod elf;
+    pub mod fdt;
+    pub mod lz4;
+    pub mod manifest;
+}
@@ -0,0 +1,501 @@
+//! Minipush: pushes a kernel image to the Raspberry Pi chainloader over a serial line, then turns
+//! into a terminal for the loaded kernel. Also signs images for loaders built with `secure-load`,
+//! and reads and writes the board's memory through the loader's monitor.
+
+mod monitor;
+mod protocol;
+mod signing;
+mod terminal;
+
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
+use serialport::SerialPort;
+
+use protocol::{Framing, Payload, Region, Transfer};
+
+/// Pushes after which minipush stops retrying a board that keeps aborting.
+const MAX_PUSHES: usize = 3;
+
+/// How long to wait for the board to request the binary at a newly agreed baud rate. The board
+/// repeats its request every few seconds, at the old rate if it missed the end of the handshake.
+const SWITCHED_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Push a kernel to the Raspberry Pi chainloader over a serial line",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    push: PushArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a key pair: KEY gets the secret key, KEY.pub the public key to build the loader
+    /// with
+    Keygen {
+        /// Where to write the secret key
+        key: PathBuf,
+
+        /// Overwrite existing key files
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Append a signature to an image, for loaders built with the `secure-load` feature
+    Sign {
+        /// Secret key from `keygen`
+        #[arg(short, long)]
+        key: PathBuf,
+
+        /// Image to sign
+        image: PathBuf,
+
+        /// Where to write the signed image [default: IMAGE.signed]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Read a word of memory or an MMIO register
+    Peek {
+        #[command(flatten)]
+        line: monitor::Line,
+
+        #[arg(value_parser = parse_number)]
+        addr: u64,
+
+        /// Access width in bytes: 1, 2, 4 or 8
+        #[arg(short, long, default_value_t = 4)]
+        width: u8,
+    },
+
+    /// Write a word of memory or an MMIO register
+    Poke {
+        #[command(flatten)]
+        line: monitor::Line,
+
+        #[arg(value_parser = parse_number)]
+        addr: u64,
+
+        #[arg(value_parser = parse_number)]
+        value: u64,
+
+        /// Access width in bytes: 1, 2, 4 or 8
+        #[arg(short, long, default_value_t = 4)]
+        width: u8,
+    },
+
+    /// Show a range of memory in hex
+    Dump {
+        #[command(flatten)]
+        line: monitor::Line,
+
+        #[arg(value_parser = parse_number)]
+        addr: u64,
+
+        /// Number of bytes
+        #[arg(value_parser = parse_number)]
+        len: u64,
+
+        /// Write the raw bytes to this file instead
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Compute the CRC-32 of a range of memory on the board
+    Crc {
+        #[command(flatten)]
+        line: monitor::Line,
+
+        #[arg(value_parser = parse_number)]
+        addr: u64,
+
+        /// Number of bytes
+        #[arg(value_parser = parse_number)]
+        len: u64,
+    },
+
+    /// Start the code at an address, then act as a terminal
+    Jump {
+        #[command(flatten)]
+        line: monitor::Line,
+
+        #[arg(value_parser = parse_number)]
+        addr: u64,
+    },
+
+    /// Make a GPIO pin an input and read its level
+    GpioGet {
+        #[command(flatten)]
+        line: monitor::Line,
+
+        pin: u8,
+
+        /// Pull resistor to enable
+        #[arg(short, long, value_enum, default_value = "none")]
+        pull: monitor::Pull,
+    },
+
+    /// Make a GPIO pin an output and drive it
+    GpioSet {
+        #[command(flatten)]
+        line: monitor::Line,
+
+        pin: u8,
+
+        #[arg(value_enum)]
+        level: monitor::Level,
+    },
+
+    /// Make a GPIO pin among GPIO0..=27 an input and wait for an event on it
+    GpioWait {
+        #[command(flatten)]
+        line: monitor::Line,
+
+        pin: u8,
+
+        #[arg(value_enum)]
+        event: monitor::Event,
+
+        /// Give up after this many seconds
+        #[arg(short, long, default_value_t = 10)]
+        timeout: u64,
+    },
+
+    /// Hand a GPIO pin to a peripheral
+    GpioAlt {
+        #[command(flatten)]
+        line: monitor::Line,
+
+        pin: u8,
+
+        /// Alternate function, 0 to 5
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=5))]
+        function: u8,
+    },
+}
+
+#[derive(Args)]
+struct PushArgs {
+    /// Serial device or PTY, e.g. /dev/ttyUSB0 or the /dev/pts/N printed by `qemu -serial pty`
+    #[arg(required = true)]
+    device: Option<PathBuf>,
+
+    /// Kernel image to push, signed if the loader was built with `secure-load`
+    #[arg(required_unless_present = "regions")]
+    image: Option<PathBuf>,
+
+    /// Load FILE to ADDR instead of pushing a single image, `,entry` if the board may start it.
+    /// Repeat for more regions. With several entry points, the board offers a boot menu
+    #[arg(
+        long = "region",
+        value_name = "NAME=FILE@ADDR[,entry]",
+        value_parser = parse_region,
+        conflicts_with_all = ["image", "initrd"]
+    )]
+    regions: Vec<RegionArg>,
+
+    /// Baud rate of the serial line
+    #[arg(short, long, default_value_t = 921_600)]
+    baud: u32,
+
+    /// Send the image as is instead of LZ4-compressed
+    #[arg(long)]
+    no_compress: bool,
+
+    /// Command line for the kernel, which finds a pointer to it in x1 (Linux: in `/chosen`)
+    #[arg(long)]
+    cmdline: Option<String>,
+
+    /// Initramfs to boot a Linux `Image` with, signed like the image
+    #[arg(long)]
+    initrd: Option<PathBuf>,
+
+    /// Agree on this baud rate with the board for the transfer, then return to `--baud`
+    #[arg(long, value_name = "BAUD")]
+    transfer_baud: Option<u32>,
+
+    /// Frame format for the transfer: 8 data bits, parity N, E or O, 1 or 2 stop bits
+    #[arg(long, value_name = "8N1", value_parser = parse_framing, default_value = "8N1")]
+    transfer_framing: Framing,
+
+    /// Use RTS/CTS flow control for the transfer, on GPIO16/17 of the board
+    #[arg(long)]
+    rts_cts: bool,
+}
+
+/// A `--region` argument.
+#[derive(Clone)]
+struct RegionArg {
+    name: String,
+    file: PathBuf,
+    addr: u64,
+    entry: bool,
+}
+
+/// A number in decimal, or in hex with `0x` in front.
+fn parse_number(arg: &str) -> Result<u64, String> {
+    match arg.strip_prefix("0x") {
+        Some(x) => u64::from_str_radix(x, 16),
+        None => arg.parse(),
+    }
+    .map_err(|x| format!("bad number {}: {}", arg, x))
+}
+
+fn parse_framing(arg: &str) -> Result<Framing, String> {
+    let error = || format!("bad frame format {}, expected e.g. 8N1 or 8E2", arg);
+    let (data_bits, rest) = arg.split_at_checked(1).ok_or_else(error)?;
+    if data_bits != "8" {
+        return Err(format!("{} data bits, the protocol needs 8", data_bits));
+    }
+
+    let parity = match rest.get(..1).map(str::to_ascii_uppercase).as_deref() {
+        Some("N") => serialport::Parity::None,
+        Some("E") => serialport::Parity::Even,
+        Some("O") => serialport::Parity::Odd,
+        _ => return Err(error()),
+    };
+    let stop_bits = match rest.get(1..) {
+        Some("1") => serialport::StopBits::One,
+        Some("2") => serialport::StopBits::Two,
+        _ => return Err(error()),
+    };
+
+    Ok(Framing { parity, stop_bits, rts_cts: false })
+}
+
+fn parse_region(arg: &str) -> Result<RegionArg, String> {
+    let (arg, entry) = match arg.strip_suffix(",entry") {
+        Some(x) => (x, true),
+        None => (arg, false),
+    };
+    let (name, rest) = arg.split_once('=').ok_or("expected NAME=FILE@ADDR")?;
+    let (file, addr) = rest.rsplit_once('@').ok_or("expected NAME=FILE@ADDR")?;
+
+    if name.is_empty() || name.len() > protocol::REGION_NAME_LEN || name.contains('\0') {
+        return Err(format!("name must be 1 to {} bytes", protocol::REGION_NAME_LEN));
+    }
+    let addr = parse_number(addr)?;
+
+    Ok(RegionArg { name: name.into(), file: file.into(), addr, entry })
+}
+
+fn open(device: &Path, baud: u32) -> Result<Box<dyn SerialPort>> {
+    if !device.exists() {
+        println!("[MP] ⏳ Waiting for {}", device.display());
+        while !device.exists() {
+            thread::sleep(Duration::from_millis(500));
+        }
+    }
+
+    let port = serialport::new(device.to_string_lossy(), baud)
+        .timeout(Duration::from_millis(100))
+        .open()
+        .with_context(|| format!("cannot open {}", device.display()))?;
+    println!("[MP] ✅ Serial connected");
+
+    Ok(port)
+}
+
+fn progress_bar(len: usize) -> ProgressBar {
+    let progress = ProgressBar::new(len as u64);
+    progress.set_style(
+        ProgressStyle::with_template(
+            "[MP] ⏩ Pushing {bytes:>9}/{total_bytes:9} [{bar:40}] {binary_bytes_per_sec} {eta}",
+        )
+        .unwrap()
+        .progress_chars("=> "),
+    );
+
+    progress
+}
+
+/// Switch the line to `baud` and `framing` for the transfer, after the board requested a binary.
+/// Returns once the board requested it again, at whatever setting the line ends up with.
+fn switch_line(port: &mut dyn SerialPort, baud: u32, framing: Framing) -> Result<()> {
+    let old = port.baud_rate()?;
+
+    if !protocol::switch_line(port, baud, framing)? {
+        println!("[MP] ⚠️  Board did not switch to {} baud, staying at {}", baud, old);
+        protocol::wait_for_request(port, None)?;
+        return Ok(());
+    }
+
+    if !protocol::wait_for_request(port, Some(SWITCHED_REQUEST_TIMEOUT))? {
+        // The board missed our final ACK and went back.
+        println!("[MP] ⚠️  Lost the board at {} baud, going back to {}", baud, old);
+        port.set_baud_rate(old)?;
+        Framing::DEFAULT.apply(port)?;
+        protocol::wait_for_request(port, None)?;
+        return Ok(());
+    }
+
+    println!("[MP] ⚡ Switched to {} baud", baud);
+    Ok(())
+}
+
+/// Push `payloads` one after the other, in a single session with the board. The transfer runs at
+/// `transfer_baud` and `framing` if they differ from what the line starts with.
+fn push(
+    port: &mut dyn SerialPort,
+    payloads: &[Payload],
+    transfer_baud: Option<u32>,
+    framing: Framing,
+) -> Result<()> {
+    let baud = port.baud_rate()?;
+    let transfer_baud = transfer_baud.unwrap_or(baud);
+    let mut switched = false;
+
+    for _ in 0..MAX_PUSHES {
+        protocol::wait_for_request(port, None)?;
+
+        // Once agreed, the board keeps the setting until the payload runs.
+        if (transfer_baud != baud || framing != Framing::DEFAULT) && !switched {
+            switch_line(port, transfer_baud, framing)?;
+            switched = true;
+        }
+        println!("[MP] 🔌 Binary requested");
+
+        let mut transfer = Transfer::Done;
+        for payload in payloads {
+            let progress = progress_bar(payload.len());
+            transfer = protocol::push(port, payload, &progress)?;
+            progress.finish();
+
+            if matches!(transfer, Transfer::Aborted) {
+                break;
+            }
+        }
+
+        match transfer {
+            Transfer::Done => {
+                // The board goes back to the default setting before it starts the payload.
+                port.set_baud_rate(baud)?;
+                Framing::DEFAULT.apply(port)?;
+                return Ok(());
+            }
+            // The board explains why and requests the binary again.
+            Transfer::Aborted => println!("[MP] ❌ Board aborted the transfer"),
+        }
+    }
+
+    bail!("board aborted {} pushes in a row", MAX_PUSHES)
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("cannot read {}", path.display()))
+}
+
+/// Push the image, then act as a terminal.
+fn run(args: PushArgs) -> Result<()> {
+    // Required unless a subcommand is given. Without regions, so is the image.
+    let device = args.device.expect("device is required");
+    let image = match &args.image {
+        Some(x) => read(x)?,
+        None => vec![],
+    };
+    let initrd = args.initrd.as_deref().map(read).transpose()?;
+
+    if args.regions.len() > protocol::MAX_REGIONS {
+        bail!("at most {} regions fit into a manifest", protocol::MAX_REGIONS);
+    }
+    if !args.regions.is_empty() && !args.regions.iter().any(|x| x.entry) {
+        bail!("none of the regions is an entry point, mark one with `,entry`");
+    }
+    let mut regions = vec![];
+    for x in &args.regions {
+        let image = read(&x.file)?;
+        regions.push(Region { name: x.name.clone(), addr: x.addr, image, entry: x.entry });
+    }
+    let manifest = protocol::manifest(&regions);
+
+    let mut payload = if regions.is_empty() {
+        Payload::new(&image, !args.no_compress)
+    } else {
+        Payload::new(&manifest, false).with_manifest()
+    };
+    if let Some(cmdline) = &args.cmdline {
+        if cmdline.len() > protocol::CMDLINE_MAX {
+            bail!("command line is longer than {} bytes", protocol::CMDLINE_MAX);
+        }
+        if cmdline.contains('\0') {
+            bail!("command line must not contain NUL characters");
+        }
+        payload = payload.with_cmdline(cmdline);
+    }
+    let mut payloads = vec![];
+    match &initrd {
+        Some(x) => payloads.extend([payload.with_initrd(), Payload::new(x, !args.no_compress)]),
+        None => payloads.push(payload),
+    }
+    payloads.extend(regions.iter().map(|x| Payload::new(&x.image, !args.no_compress)));
+
+    for payload in &payloads {
+        if payload.is_compressed() {
+            println!("[MP] 🗜  Compressed {} bytes to {} bytes", payload.image_len(), payload.len());
+        }
+    }
+
+    let mut port = open(&device, args.baud)?;
+
+    let framing = Framing { rts_cts: args.rts_cts, ..args.transfer_framing };
+    push(port.as_mut(), &payloads, args.transfer_baud, framing)?;
+    let total: usize = payloads.iter().map(Payload::image_len).sum();
+    println!("[MP] 🦀 Pushed {} bytes", total);
+
+    terminal::run(port.as_mut())
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(Command::Keygen { key, force }) => {
+            signing::keygen(&key, force)?;
+            let public = signing::public_key_path(&key);
+            println!("[MP] 🔑 Secret key in {}, public key in {}", key.display(), public.display());
+        }
+        Some(Command::Sign { key, image, output }) => {
+            let signed = signing::sign(&read(&image)?, &key)?;
+            let output = output.unwrap_or_else(|| {
+                let mut path = image.into_os_string();
+                path.push(".signed");
+                path.into()
+            });
+            std::fs::write(&output, signed)
+                .with_context(|| format!("cannot write {}", output.display()))?;
+            println!("[MP] 🔏 Signed image in {}", output.display());
+        }
+        Some(Command::Peek { line, addr, width }) => monitor::peek(&line, addr, width)?,
+        Some(Command::Poke { line, addr, value, width }) => {
+            monitor::poke(&line, addr, value, width)?
+        }
+        Some(Command::Dump { line, addr, len, output }) => {
+            monitor::dump(&line, addr, len, output.as_deref())?
+        }
+        Some(Command::Crc { line, addr, len }) => monitor::crc(&line, addr, len)?,
+        Some(Command::Jump { line, addr }) => monitor::jump(&line, addr)?,
+        Some(Command::GpioGet { line, pin, pull }) => monitor::gpio_get(&line, pin, pull)?,
+        Some(Command::GpioSet { line, pin, level }) => monitor::gpio_set(&line, pin, level)?,
+        Some(Command::GpioWait { line, pin, event, timeout }) => {
+            monitor::gpio_wait(&line, pin, event, Duration::from_secs(timeout))?
+        }
+        Some(Command::GpioAlt { line, pin, function }) => {
+            monitor::gpio_alt(&line, pin, function)?
+        }
+        None => run(cli.push)?,
+    }
+
+    Ok(())
+}
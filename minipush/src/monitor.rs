@@ -0,0 +1,193 @@
+//! Memory monitor commands, see `src/loader/monitor.rs` of the kernel. Each one waits for the
+//! board to request a binary, runs and leaves the board waiting for the next.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use indicatif::{ProgressBar, ProgressStyle};
+use serialport::SerialPort;
+
+use crate::protocol::{self, Command};
+use crate::terminal;
+
+/// How long the board gets to explain why it refused a command.
+const REASON_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bytes per line of a hex dump.
+const DUMP_LINE: usize = 16;
+
+/// Pull resistor of a GPIO input.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Pull {
+    None = 0,
+    Up = 1,
+    Down = 2,
+}
+
+/// What to drive a GPIO output to.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Level {
+    Low = 0,
+    High = 1,
+    /// The opposite of the level the pin has now
+    Toggle = 2,
+}
+
+/// A GPIO event to wait for.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Event {
+    Rising = 0,
+    Falling = 1,
+    High = 2,
+    Low = 3,
+    /// Rising edges shorter than a clock cycle
+    AsyncRising = 4,
+    /// Falling edges shorter than a clock cycle
+    AsyncFalling = 5,
+}
+
+/// The serial line to the board.
+#[derive(Args)]
+pub struct Line {
+    /// Serial device or PTY the board is on
+    device: PathBuf,
+
+    /// Baud rate of the serial line
+    #[arg(short, long, default_value_t = 921_600)]
+    baud: u32,
+}
+
+/// Open the line and wait for the board to listen.
+fn connect(line: &Line) -> Result<Box<dyn SerialPort>> {
+    let mut port = super::open(&line.device, line.baud)?;
+    protocol::wait_for_request(port.as_mut(), None)?;
+
+    Ok(port)
+}
+
+fn send(port: &mut dyn SerialPort, command: Command, width: u8, addr: u64, arg: u64) -> Result<()> {
+    if !protocol::send_command(port, command, width, addr, arg)? {
+        // The reason shows on the console, followed by the next request.
+        protocol::wait_for_request(port, Some(REASON_TIMEOUT))?;
+        bail!("board refused the command");
+    }
+
+    Ok(())
+}
+
+/// Print `width` bytes read at `addr`.
+pub fn peek(line: &Line, addr: u64, width: u8) -> Result<()> {
+    let mut port = connect(line)?;
+    send(port.as_mut(), Command::Peek, width, addr, 0)?;
+    let value = protocol::read_value(port.as_mut(), Command::Peek)?;
+
+    println!("{:#x}: {:#0digits$x}", addr, value, digits = 2 + 2 * usize::from(width));
+    Ok(())
+}
+
+/// Write the low `width` bytes of `value` to `addr`.
+pub fn poke(line: &Line, addr: u64, value: u64, width: u8) -> Result<()> {
+    let mut port = connect(line)?;
+    send(port.as_mut(), Command::Poke, width, addr, value)?;
+
+    println!("[MP] ✅ Wrote {:#x} to {:#x}", value, addr);
+    Ok(())
+}
+
+fn hexdump(addr: u64, data: &[u8]) {
+    for (offset, line) in (0..).step_by(DUMP_LINE).zip(data.chunks(DUMP_LINE)) {
+        let hex: Vec<String> = line.iter().map(|x| format!("{:02x}", x)).collect();
+        let text: String = line
+            .iter()
+            .map(|&x| if x.is_ascii_graphic() || x == b' ' { x as char } else { '.' })
+            .collect();
+
+        println!("{:#010x}  {:<48} |{}|", addr + offset, hex.join(" "), text);
+    }
+}
+
+/// Print `len` bytes at `addr` as a hex dump, or write them to `output`.
+pub fn dump(line: &Line, addr: u64, len: u64, output: Option<&Path>) -> Result<()> {
+    let mut port = connect(line)?;
+    send(port.as_mut(), Command::Dump, 1, addr, len)?;
+
+    let progress = ProgressBar::new(len);
+    progress.set_style(
+        ProgressStyle::with_template("[MP] ⏪ Reading {bytes:>9}/{total_bytes:9} [{bar:40}] {eta}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    let data = protocol::read_dump(port.as_mut(), len as usize, &progress)?;
+    progress.finish_and_clear();
+
+    match output {
+        Some(path) => std::fs::write(path, data)
+            .with_context(|| format!("cannot write {}", path.display()))?,
+        None => hexdump(addr, &data),
+    }
+    Ok(())
+}
+
+/// Print the CRC-32 of `len` bytes at `addr`, as computed by the board.
+pub fn crc(line: &Line, addr: u64, len: u64) -> Result<()> {
+    let mut port = connect(line)?;
+    send(port.as_mut(), Command::Crc, 1, addr, len)?;
+    let crc = protocol::read_value(port.as_mut(), Command::Crc)?;
+
+    println!("{:#010x}", crc);
+    Ok(())
+}
+
+/// Make GPIO `pin` an input with `pull` and print its level.
+pub fn gpio_get(line: &Line, pin: u8, pull: Pull) -> Result<()> {
+    let mut port = connect(line)?;
+    send(port.as_mut(), Command::GpioGet, 1, pin.into(), pull as u64)?;
+    let level = protocol::read_value(port.as_mut(), Command::GpioGet)?;
+
+    println!("GPIO{}: {}", pin, if level != 0 { "high" } else { "low" });
+    Ok(())
+}
+
+/// Make GPIO `pin` an output at `level`.
+pub fn gpio_set(line: &Line, pin: u8, level: Level) -> Result<()> {
+    let mut port = connect(line)?;
+    send(port.as_mut(), Command::GpioSet, 1, pin.into(), level as u64)?;
+
+    println!("[MP] ✅ Set GPIO{}", pin);
+    Ok(())
+}
+
+/// Hand GPIO `pin` to the peripheral behind alternate function `function`.
+pub fn gpio_alt(line: &Line, pin: u8, function: u8) -> Result<()> {
+    let mut port = connect(line)?;
+    send(port.as_mut(), Command::GpioAlt, 1, pin.into(), function.into())?;
+
+    println!("[MP] ✅ GPIO{} switched to ALT{}", pin, function);
+    Ok(())
+}
+
+/// Make GPIO `pin` an input and wait up to `timeout` for `event` on it.
+pub fn gpio_wait(line: &Line, pin: u8, event: Event, timeout: Duration) -> Result<()> {
+    let millis = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX).min(u32::MAX.into());
+
+    let mut port = connect(line)?;
+    send(port.as_mut(), Command::GpioWait, 1, pin.into(), event as u64 | millis << 32)?;
+    let seen = protocol::read_value_within(port.as_mut(), timeout + REASON_TIMEOUT)?;
+
+    if seen == 0 {
+        bail!("no event on GPIO{} within {:?}", pin, timeout);
+    }
+    println!("[MP] ✅ Event on GPIO{}", pin);
+    Ok(())
+}
+
+/// Have the board start the code at `addr`, then act as a terminal.
+pub fn jump(line: &Line, addr: u64) -> Result<()> {
+    let mut port = connect(line)?;
+    send(port.as_mut(), Command::Jump, 1, addr, 0)?;
+
+    println!("[MP] 🦘 Jumped to {:#x}", addr);
+    terminal::run(port.as_mut())
+}
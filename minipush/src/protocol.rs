@@ -0,0 +1,641 @@
+//! Host side of the chainloader wire protocol, see `src/loader.rs` of the kernel.
+
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use indicatif::ProgressBar;
+use serialport::{ClearBuffer, FlowControl, Parity, SerialPort, StopBits};
+
+const MAGIC: [u8; 4] = *b"RPML";
+const BLOCK_SIZE: usize = 1024;
+
+const FLAG_LZ4: u32 = 1 << 0;
+const FLAG_CMDLINE: u32 = 1 << 1;
+const FLAG_INITRD: u32 = 1 << 2;
+const FLAG_MANIFEST: u32 = 1 << 3;
+
+/// Frame index of the command line, right before block 0.
+const CMDLINE_INDEX: u32 = u32::MAX;
+
+/// Longest command line the board takes.
+pub const CMDLINE_MAX: usize = BLOCK_SIZE - 1;
+
+/// Longest region name in a manifest.
+pub const REGION_NAME_LEN: usize = 16;
+
+/// Most regions a manifest may list.
+pub const MAX_REGIONS: usize = 16;
+
+const REGION_ENTRY: u32 = 1 << 0;
+
+const BAUD_MAGIC: [u8; 4] = *b"BAUD";
+const FRAMING_EVEN: u8 = 1;
+const FRAMING_ODD: u8 = 2;
+const FRAMING_TWO_STOP_BITS: u8 = 1 << 2;
+const FRAMING_RTS_CTS: u8 = 1 << 3;
+const PROBE: [u8; 16] = [
+    0x55, 0xAA, 0x33, 0xCC, 0x0F, 0xF0, 0x5A, 0xA5, 0x00, 0xFF, 0x69, 0x96, 0x3C, 0xC3, 0x99, 0x66,
+];
+
+const MONITOR_MAGIC: [u8; 4] = *b"MCMD";
+
+const SOH: u8 = 0x01;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+
+/// Sends per header or block before giving up. The board normally aborts first.
+const MAX_ATTEMPTS: usize = 16;
+
+/// How long to wait for the answer to a frame.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long to wait for the final checksum verdict, which covers the whole image. Loaders built
+/// with `secure-load` check its signature as well, with caches still off.
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long the board gets to echo the probe at the new baud rate.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Time for the board to reprogram its UART after acknowledging a baud rate request.
+const SWITCH_DELAY: Duration = Duration::from_millis(50);
+
+/// Monitor commands, see `src/loader/monitor.rs` of the kernel.
+#[derive(Clone, Copy)]
+pub enum Command {
+    Peek = 1,
+    Poke = 2,
+    Dump = 3,
+    Crc = 4,
+    Jump = 5,
+    GpioGet = 6,
+    GpioSet = 7,
+    GpioAlt = 8,
+    GpioWait = 9,
+}
+
+/// Frame format of the line. Characters are always 8 bits wide, the protocol is binary.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Framing {
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub rts_cts: bool,
+}
+
+impl Framing {
+    /// 8N1 without flow control, which the board starts with.
+    pub const DEFAULT: Self = Self { parity: Parity::None, stop_bits: StopBits::One, rts_cts: false };
+
+    /// The format `port` is set to.
+    fn of(port: &dyn SerialPort) -> Result<Self> {
+        Ok(Self {
+            parity: port.parity()?,
+            stop_bits: port.stop_bits()?,
+            rts_cts: port.flow_control()? == FlowControl::Hardware,
+        })
+    }
+
+    /// The format as sent in a baud rate request, see `src/loader/baud.rs` of the kernel.
+    fn to_byte(self) -> u8 {
+        let parity = match self.parity {
+            Parity::None => 0,
+            Parity::Even => FRAMING_EVEN,
+            Parity::Odd => FRAMING_ODD,
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => FRAMING_TWO_STOP_BITS,
+        };
+        let rts_cts = if self.rts_cts { FRAMING_RTS_CTS } else { 0 };
+
+        parity | stop_bits | rts_cts
+    }
+
+    /// Switch `port` to this format.
+    pub fn apply(self, port: &mut dyn SerialPort) -> Result<()> {
+        let flow_control = if self.rts_cts { FlowControl::Hardware } else { FlowControl::None };
+
+        port.set_parity(self.parity)?;
+        port.set_stop_bits(self.stop_bits)?;
+        port.set_flow_control(flow_control)?;
+        Ok(())
+    }
+}
+
+/// How the board ended the transfer.
+pub enum Transfer {
+    Done,
+    /// The board sent `CAN`. Its reason follows on the console.
+    Aborted,
+}
+
+enum Response {
+    Ack,
+    Nak,
+    Can,
+    Timeout,
+}
+
+/// Echo everything the board prints until it requests a binary with three `0x03` bytes.
+///
+/// Returns `false` if `timeout` passed without a request.
+pub fn wait_for_request(port: &mut dyn SerialPort, timeout: Option<Duration>) -> Result<bool> {
+    let deadline = timeout.map(|x| Instant::now() + x);
+    let mut stdout = io::stdout();
+    let mut count = 0;
+    let mut byte = [0u8; 1];
+
+    port.set_timeout(Duration::from_millis(100))?;
+    loop {
+        if deadline.is_some_and(|x| Instant::now() >= x) {
+            return Ok(false);
+        }
+
+        match port.read(&mut byte) {
+            Ok(0) => continue,
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e.into()),
+        }
+
+        // The board cancels with two `CAN`s, only the first one is consumed by the protocol.
+        if byte[0] == CAN {
+            continue;
+        }
+
+        if byte[0] == 0x03 {
+            count += 1;
+            if count == 3 {
+                return Ok(true);
+            }
+            continue;
+        }
+
+        // Only a complete request counts. Print whatever partial one came before.
+        for _ in 0..count {
+            stdout.write_all(&[0x03])?;
+        }
+        count = 0;
+        stdout.write_all(&byte)?;
+        stdout.flush()?;
+    }
+}
+
+fn read_response(port: &mut dyn SerialPort, timeout: Duration) -> Result<Response> {
+    port.set_timeout(timeout)?;
+    let mut byte = [0u8; 1];
+
+    loop {
+        match port.read(&mut byte) {
+            Ok(0) => continue,
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => return Ok(Response::Timeout),
+            Err(e) => return Err(e.into()),
+        }
+
+        match byte[0] {
+            ACK => return Ok(Response::Ack),
+            NAK => return Ok(Response::Nak),
+            CAN => return Ok(Response::Can),
+            // Line noise, keep waiting for a real answer.
+            _ => continue,
+        }
+    }
+}
+
+/// The board did not answer, most likely because it lost bytes and is still waiting for the rest
+/// of a frame. Pad with zeros until the frame is complete, then drop the `NAK` this provokes so
+/// it is not mistaken for the answer to the retransmission.
+fn resync(port: &mut dyn SerialPort) -> Result<()> {
+    port.write_all(&[0u8; BLOCK_SIZE + 16])?;
+    port.flush()?;
+    thread::sleep(Duration::from_millis(100));
+    port.clear(ClearBuffer::Input)?;
+
+    Ok(())
+}
+
+/// Send `frame` until the board acknowledges it.
+///
+/// Returns `false` if the board aborted the transfer.
+fn send_frame(port: &mut dyn SerialPort, frame: &[u8], what: &str) -> Result<bool> {
+    for _ in 0..MAX_ATTEMPTS {
+        port.write_all(frame)?;
+        port.flush()?;
+
+        match read_response(port, RESPONSE_TIMEOUT)? {
+            Response::Ack => return Ok(true),
+            Response::Nak => continue,
+            Response::Can => return Ok(false),
+            Response::Timeout => resync(port)?,
+        }
+    }
+
+    bail!("{} not accepted after {} attempts", what, MAX_ATTEMPTS)
+}
+
+/// Wait for the board to echo `PROBE`, skipping anything garbled in front of it.
+fn read_echo(port: &mut dyn SerialPort) -> Result<bool> {
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    let mut received = Vec::new();
+    let mut buf = [0u8; 64];
+
+    port.set_timeout(Duration::from_millis(100))?;
+    while Instant::now() < deadline {
+        match port.read(&mut buf) {
+            Ok(n) => received.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e.into()),
+        }
+
+        if received.windows(PROBE.len()).any(|x| x == PROBE) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn baud_frame(baud: u32, framing: Framing) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(13);
+    frame.extend_from_slice(&BAUD_MAGIC);
+    frame.extend_from_slice(&baud.to_le_bytes());
+    frame.push(framing.to_byte());
+    let crc = crc32fast::hash(&frame);
+    frame.extend_from_slice(&crc.to_le_bytes());
+
+    frame
+}
+
+/// Ask a board that just requested a binary to continue at `baud` with `framing`.
+///
+/// On success, both ends run at the new setting and the board requests the binary again.
+/// Otherwise both stay at, or went back to, the setting the port had before.
+pub fn switch_line(port: &mut dyn SerialPort, baud: u32, framing: Framing) -> Result<bool> {
+    let old = (port.baud_rate()?, Framing::of(port)?);
+
+    let frame = baud_frame(baud, framing);
+
+    port.write_all(&frame)?;
+    port.flush()?;
+    match read_response(port, RESPONSE_TIMEOUT)? {
+        Response::Ack => {}
+        _ => return Ok(false),
+    }
+
+    port.set_baud_rate(baud)?;
+    framing.apply(port)?;
+    thread::sleep(SWITCH_DELAY);
+    port.clear(ClearBuffer::Input)?;
+
+    port.write_all(&PROBE)?;
+    port.flush()?;
+    if read_echo(port)? {
+        port.write_all(&[ACK])?;
+        port.flush()?;
+        return Ok(true);
+    }
+
+    // The board falls back on its own once the probe times out.
+    port.set_baud_rate(old.0)?;
+    old.1.apply(port)?;
+    Ok(false)
+}
+
+fn read_exact(port: &mut dyn SerialPort, buf: &mut [u8], timeout: Duration) -> Result<()> {
+    port.set_timeout(timeout)?;
+    match port.read_exact(buf) {
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => bail!("board stopped answering"),
+        x => Ok(x?),
+    }
+}
+
+/// Read the value an accepted `Command::Peek`, `Command::Crc` or `Command::GpioGet` results in.
+pub fn read_value(port: &mut dyn SerialPort, command: Command) -> Result<u64> {
+    // For a CRC, the board reads the whole range first.
+    let timeout = match command {
+        Command::Crc => VERIFY_TIMEOUT,
+        _ => RESPONSE_TIMEOUT,
+    };
+
+    read_value_within(port, timeout)
+}
+
+/// Read the value an accepted command results in, which the board sends within `timeout`.
+pub fn read_value_within(port: &mut dyn SerialPort, timeout: Duration) -> Result<u64> {
+    let mut raw = [0u8; 12];
+    read_exact(port, &mut raw, timeout)?;
+
+    let (value, crc) = raw.split_at(8);
+    if crc32fast::hash(value).to_le_bytes() != crc {
+        bail!("reply damaged on the line");
+    }
+
+    Ok(u64::from_le_bytes(value.try_into().unwrap()))
+}
+
+/// Send a monitor command to a board that just requested a binary. Damaged commands are sent
+/// again once the board requested the binary again.
+///
+/// Returns `false` if the board refused the command. It explains why on the console.
+pub fn send_command(
+    port: &mut dyn SerialPort,
+    command: Command,
+    width: u8,
+    addr: u64,
+    arg: u64,
+) -> Result<bool> {
+    let mut frame = Vec::with_capacity(26);
+    frame.extend_from_slice(&MONITOR_MAGIC);
+    frame.extend_from_slice(&[command as u8, width]);
+    frame.extend_from_slice(&addr.to_le_bytes());
+    frame.extend_from_slice(&arg.to_le_bytes());
+    let crc = crc32fast::hash(&frame);
+    frame.extend_from_slice(&crc.to_le_bytes());
+
+    for _ in 0..MAX_ATTEMPTS {
+        port.write_all(&frame)?;
+        port.flush()?;
+
+        match read_response(port, RESPONSE_TIMEOUT)? {
+            Response::Ack => return Ok(true),
+            Response::Can => return Ok(false),
+            Response::Nak => {}
+            // The board gives up on the rest of the command by itself.
+            Response::Timeout => {}
+        }
+        wait_for_request(port, None)?;
+    }
+
+    bail!("command not accepted after {} attempts", MAX_ATTEMPTS)
+}
+
+/// Read the `len` bytes an accepted `Command::Dump` results in.
+pub fn read_dump(
+    port: &mut dyn SerialPort,
+    len: usize,
+    progress: &ProgressBar,
+) -> Result<Vec<u8>> {
+    let mut data = vec![0u8; len];
+    for chunk in data.chunks_mut(BLOCK_SIZE) {
+        read_exact(port, chunk, RESPONSE_TIMEOUT)?;
+        progress.inc(chunk.len() as u64);
+    }
+
+    let mut crc = [0u8; 4];
+    read_exact(port, &mut crc, RESPONSE_TIMEOUT)?;
+    if crc32fast::hash(&data).to_le_bytes() != crc {
+        bail!("dump damaged on the line");
+    }
+
+    Ok(data)
+}
+
+/// An image the board loads to an address of our choosing, as listed in a manifest.
+pub struct Region {
+    pub name: String,
+    pub addr: u64,
+    pub image: Vec<u8>,
+    /// The board may jump to the start of the region.
+    pub entry: bool,
+}
+
+/// The manifest listing `regions`, see `src/loader/manifest.rs` of the kernel.
+pub fn manifest(regions: &[Region]) -> Vec<u8> {
+    let mut manifest = vec![];
+    for region in regions {
+        let mut name = [0u8; REGION_NAME_LEN];
+        name[..region.name.len()].copy_from_slice(region.name.as_bytes());
+        let flags = if region.entry { REGION_ENTRY } else { 0 };
+
+        manifest.extend_from_slice(&name);
+        manifest.extend_from_slice(&region.addr.to_le_bytes());
+        manifest.extend_from_slice(&(region.image.len() as u32).to_le_bytes());
+        manifest.extend_from_slice(&crc32fast::hash(&region.image).to_le_bytes());
+        manifest.extend_from_slice(&flags.to_le_bytes());
+    }
+
+    manifest
+}
+
+/// What goes over the wire for an image.
+pub struct Payload<'a> {
+    image: &'a [u8],
+    data: Vec<u8>,
+    flags: u32,
+    cmdline: Option<&'a str>,
+}
+
+impl<'a> Payload<'a> {
+    /// Prepare `image` for sending, LZ4-compressed if `compress` is set and that actually helps.
+    pub fn new(image: &'a [u8], compress: bool) -> Self {
+        if compress {
+            let data = lz4_flex::block::compress(image);
+            if data.len() < image.len() {
+                return Self { image, data, flags: FLAG_LZ4, cmdline: None };
+            }
+        }
+
+        Self { image, data: image.to_vec(), flags: 0, cmdline: None }
+    }
+
+    /// Hand `cmdline` to the payload along with the image.
+    pub fn with_cmdline(mut self, cmdline: &'a str) -> Self {
+        self.cmdline = Some(cmdline);
+        self.flags |= FLAG_CMDLINE;
+        self
+    }
+
+    /// Announce an initramfs as the next payload. The image must be a Linux `Image`.
+    pub fn with_initrd(mut self) -> Self {
+        self.flags |= FLAG_INITRD;
+        self
+    }
+
+    /// Send a manifest instead of an image. The regions it lists follow as payloads of their
+    /// own.
+    pub fn with_manifest(mut self) -> Self {
+        self.flags |= FLAG_MANIFEST;
+        self
+    }
+
+    /// Bytes to transfer.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Bytes once unpacked on the board.
+    pub fn image_len(&self) -> usize {
+        self.image.len()
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.flags & FLAG_LZ4 != 0
+    }
+}
+
+fn header_frame(payload: &Payload) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(24);
+    frame.extend_from_slice(&MAGIC);
+    frame.extend_from_slice(&payload.flags.to_le_bytes());
+    frame.extend_from_slice(&(payload.data.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&(payload.image.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32fast::hash(payload.image).to_le_bytes());
+
+    let header_crc = crc32fast::hash(&frame);
+    frame.extend_from_slice(&header_crc.to_le_bytes());
+
+    frame
+}
+
+fn block_frame(index: u32, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(data.len() + 11);
+    frame.push(SOH);
+    frame.extend_from_slice(&index.to_le_bytes());
+    frame.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    frame.extend_from_slice(data);
+
+    let crc = crc32fast::hash(&frame[1..]);
+    frame.extend_from_slice(&crc.to_le_bytes());
+
+    frame
+}
+
+/// Push `payload` to a board that just requested a binary.
+pub fn push(port: &mut dyn SerialPort, payload: &Payload, progress: &ProgressBar) -> Result<Transfer> {
+    if u32::try_from(payload.image.len()).is_err() {
+        bail!("image is too big: {} bytes", payload.image.len());
+    }
+
+    if !send_frame(port, &header_frame(payload), "header")? {
+        return Ok(Transfer::Aborted);
+    }
+
+    if let Some(cmdline) = payload.cmdline {
+        let frame = block_frame(CMDLINE_INDEX, cmdline.as_bytes());
+        if !send_frame(port, &frame, "command line")? {
+            return Ok(Transfer::Aborted);
+        }
+    }
+
+    for (index, data) in payload.data.chunks(BLOCK_SIZE).enumerate() {
+        let frame = block_frame(index as u32, data);
+        if !send_frame(port, &frame, &format!("block {}", index))? {
+            return Ok(Transfer::Aborted);
+        }
+        progress.inc(data.len() as u64);
+    }
+
+    match read_response(port, VERIFY_TIMEOUT)? {
+        Response::Ack => Ok(Transfer::Done),
+        Response::Can => Ok(Transfer::Aborted),
+        Response::Nak | Response::Timeout => bail!("board did not confirm the image checksum"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(frame: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(frame[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// The CRC-32 in the last four bytes of `frame`, and that of the bytes from `start` up to it.
+    fn crcs(frame: &[u8], start: usize) -> (u32, u32) {
+        let end = frame.len() - 4;
+        (u32_at(frame, end), crc32fast::hash(&frame[start..end]))
+    }
+
+    #[test]
+    fn header_frame_describes_the_payload() {
+        let image = [0x42u8; 4096];
+        let payload = Payload::new(&image, true).with_cmdline("console=ttyAMA0").with_initrd();
+        assert!(payload.is_compressed());
+
+        let frame = header_frame(&payload);
+        assert_eq!(frame.len(), 24);
+        assert_eq!(frame[..4], MAGIC);
+        assert_eq!(u32_at(&frame, 4), FLAG_LZ4 | FLAG_CMDLINE | FLAG_INITRD);
+        assert_eq!(u32_at(&frame, 8) as usize, payload.len());
+        assert_eq!(u32_at(&frame, 12), 4096);
+        assert_eq!(u32_at(&frame, 16), crc32fast::hash(&image));
+
+        let (sent, computed) = crcs(&frame, 0);
+        assert_eq!(sent, computed);
+    }
+
+    #[test]
+    fn incompressible_images_go_as_they_are() {
+        let image: Vec<u8> = (0..=255).collect();
+        let payload = Payload::new(&image, true).with_manifest();
+        assert!(!payload.is_compressed());
+        assert_eq!(payload.len(), image.len());
+
+        let frame = header_frame(&payload);
+        assert_eq!(u32_at(&frame, 4), FLAG_MANIFEST);
+        assert_eq!(u32_at(&frame, 8), 256);
+    }
+
+    #[test]
+    fn block_frame_checksums_all_but_soh() {
+        let frame = block_frame(7, b"abc");
+        assert_eq!(frame.len(), 1 + 4 + 2 + 3 + 4);
+        assert_eq!(frame[0], SOH);
+        assert_eq!(u32_at(&frame, 1), 7);
+        assert_eq!(frame[5..7], 3u16.to_le_bytes());
+        assert_eq!(&frame[7..10], b"abc");
+
+        let (sent, computed) = crcs(&frame, 1);
+        assert_eq!(sent, computed);
+
+        let frame = block_frame(CMDLINE_INDEX, &[0; BLOCK_SIZE]);
+        assert_eq!(u32_at(&frame, 1), u32::MAX);
+        assert_eq!(frame[5..7], (BLOCK_SIZE as u16).to_le_bytes());
+    }
+
+    #[test]
+    fn framing_byte_matches_the_board() {
+        assert_eq!(Framing::DEFAULT.to_byte(), 0);
+
+        let framing = Framing { parity: Parity::Even, stop_bits: StopBits::One, rts_cts: true };
+        assert_eq!(framing.to_byte(), 0b1001);
+        let framing = Framing { parity: Parity::Odd, stop_bits: StopBits::Two, rts_cts: false };
+        assert_eq!(framing.to_byte(), 0b0110);
+    }
+
+    #[test]
+    fn baud_frame_carries_rate_and_framing() {
+        let framing = Framing { parity: Parity::Odd, stop_bits: StopBits::One, rts_cts: false };
+        let frame = baud_frame(921_600, framing);
+        assert_eq!(frame.len(), 13);
+        assert_eq!(frame[..4], BAUD_MAGIC);
+        assert_eq!(u32_at(&frame, 4), 921_600);
+        assert_eq!(frame[8], FRAMING_ODD);
+
+        let (sent, computed) = crcs(&frame, 0);
+        assert_eq!(sent, computed);
+    }
+
+    #[test]
+    fn manifest_lists_every_region() {
+        let regions = [
+            Region { name: "kernel".into(), addr: 0x8_0000, image: vec![1; 10], entry: true },
+            Region { name: "dtb".into(), addr: 0x200_0000, image: vec![2; 3], entry: false },
+        ];
+
+        let raw = manifest(&regions);
+        assert_eq!(raw.len(), 2 * (REGION_NAME_LEN + 20));
+
+        let (kernel, dtb) = raw.split_at(REGION_NAME_LEN + 20);
+        assert_eq!(&kernel[..7], b"kernel\0");
+        assert_eq!(kernel[REGION_NAME_LEN..REGION_NAME_LEN + 8], 0x8_0000u64.to_le_bytes());
+        assert_eq!(u32_at(kernel, REGION_NAME_LEN + 8), 10);
+        assert_eq!(u32_at(kernel, REGION_NAME_LEN + 12), crc32fast::hash(&[1; 10]));
+        assert_eq!(u32_at(kernel, REGION_NAME_LEN + 16), REGION_ENTRY);
+        assert_eq!(&dtb[..4], b"dtb\0");
+        assert_eq!(u32_at(dtb, REGION_NAME_LEN + 16), 0);
+    }
+}
@@ -0,0 +1,66 @@
+//! Ed25519 signatures for loaders built with the `secure-load` feature, see
+//! `src/loader/signature.rs` of the kernel.
+//!
+//! A signed image is the image, its signature and `MAGIC`. Keys are stored raw: 64 bytes for the
+//! secret key, 32 bytes for the public key the loader embeds.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use ed25519_compact::{KeyPair, Noise, SecretKey};
+
+/// End of a signed image: "RPSG".
+const MAGIC: [u8; 4] = *b"RPSG";
+
+/// Path of the public key that belongs to the secret key at `secret`.
+pub fn public_key_path(secret: &Path) -> PathBuf {
+    let mut path = secret.as_os_str().to_owned();
+    path.push(".pub");
+    path.into()
+}
+
+/// Write a new key pair to `secret` and `public_key_path(secret)`.
+pub fn keygen(secret: &Path, force: bool) -> Result<()> {
+    let public = public_key_path(secret);
+    if !force && (secret.exists() || public.exists()) {
+        bail!("{} or {} exists already", secret.display(), public.display());
+    }
+
+    let keys = KeyPair::generate();
+
+    // Only readable by the owner.
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(secret)
+        .and_then(|mut x| x.write_all(keys.sk.as_ref()))
+        .with_context(|| format!("cannot write {}", secret.display()))?;
+    fs::write(&public, keys.pk.as_ref())
+        .with_context(|| format!("cannot write {}", public.display()))?;
+
+    Ok(())
+}
+
+/// `image` with the signature made with the secret key at `secret` appended.
+pub fn sign(image: &[u8], secret: &Path) -> Result<Vec<u8>> {
+    let raw = fs::read(secret).with_context(|| format!("cannot read {}", secret.display()))?;
+    let key = SecretKey::from_slice(&raw)
+        .and_then(|x| x.validate_public_key(&x.public_key()).map(|_| x))
+        .with_context(|| format!("{} is not a secret key", secret.display()))?;
+
+    if image.ends_with(&MAGIC) {
+        bail!("image is signed already");
+    }
+
+    let signature = key.sign(image, Some(Noise::generate()));
+    let mut signed = Vec::with_capacity(image.len() + signature.len() + MAGIC.len());
+    signed.extend_from_slice(image);
+    signed.extend_from_slice(signature.as_ref());
+    signed.extend_from_slice(&MAGIC);
+
+    Ok(signed)
+}
@@ -0,0 +1,79 @@
+//! Minimal interactive terminal on the serial line.
+
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::terminal;
+use serialport::SerialPort;
+
+fn key_bytes(key: KeyEvent) -> Option<Vec<u8>> {
+    let bytes = match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            vec![(c.to_ascii_lowercase() as u8) & 0x1F]
+        }
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Backspace => vec![0x08],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::Esc => vec![0x1B],
+        _ => return None,
+    };
+
+    Some(bytes)
+}
+
+/// Forward the serial line to stdout and keystrokes to the serial line until Ctrl-C.
+pub fn run(port: &mut dyn SerialPort) -> Result<()> {
+    let mut rx = port.try_clone()?;
+    rx.set_timeout(Duration::from_millis(100))?;
+
+    thread::spawn(move || {
+        let mut stdout = io::stdout();
+        let mut buf = [0u8; 256];
+
+        loop {
+            let n = match rx.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(_) => {
+                    print!("\r\n[MP] Serial connection lost\r\n");
+                    let _ = stdout.flush();
+                    return;
+                }
+            };
+
+            // Raw mode does not return the carriage on its own.
+            for &b in &buf[..n] {
+                let _ = match b {
+                    b'\n' => stdout.write_all(b"\r\n"),
+                    _ => stdout.write_all(&[b]),
+                };
+            }
+            let _ = stdout.flush();
+        }
+    });
+
+    terminal::enable_raw_mode()?;
+    let result = forward_keys(port);
+    terminal::disable_raw_mode()?;
+    println!();
+
+    result
+}
+
+fn forward_keys(port: &mut dyn SerialPort) -> Result<()> {
+    loop {
+        if let Event::Key(key) = event::read()? {
+            if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                return Ok(());
+            }
+
+            if let Some(bytes) = key_bytes(key) {
+                port.write_all(&bytes)?;
+            }
+        }
+    }
+}
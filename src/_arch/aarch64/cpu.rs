@@ -1,18 +1,22 @@
-use cortex_a::asm;
+use cortex_a::{asm, registers::*};
+use tock_registers::interfaces::Readable;
+use crate::cpu::PrivilegeLevel;
 
 pub use asm::nop;
 
-#[cfg(feature = "bsp-rpi-3")]
-#[inline(always)]
-pub fn spin_for_cycles(delay: usize) {
-    for _ in 0..delay {
-        asm::nop();
-    }
-}
-
 #[inline]
 pub fn wait_forever() -> ! {
     loop {
         asm::wfe();
     }
-}
\ No newline at end of file
+}
+
+/// The exception level the CPU runs at, and its name.
+pub fn current_privilege_level() -> (PrivilegeLevel, &'static str) {
+    match CurrentEL.read_as_enum(CurrentEL::EL) {
+        Some(CurrentEL::EL::Value::EL2) => (PrivilegeLevel::Hypervisor, "EL2"),
+        Some(CurrentEL::EL::Value::EL1) => (PrivilegeLevel::Kernel, "EL1"),
+        Some(CurrentEL::EL::Value::EL0) => (PrivilegeLevel::User, "EL0"),
+        _ => (PrivilegeLevel::Unknown, "Unknown"),
+    }
+}
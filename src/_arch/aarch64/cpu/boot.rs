@@ -1,10 +1,78 @@
-
 //! arm assembly
-use core::arch::global_asm;
+use core::arch::{asm, global_asm};
+use core::cell::UnsafeCell;
+use cortex_a::registers::*;
+use tock_registers::interfaces::{Readable, Writeable};
+use crate::cpu::boot::BootArgs;
+
+global_asm!(
+    include_str!("boot.s"),
+    CONST_CORE_ID_MASK = const 0b11,
+    CONST_REENTRY_MAGIC = const crate::cpu::boot::REENTRY_MAGIC
+);
+
+// Symbols from the linker script.
+extern "Rust" {
+    static __boot_core_stack_end_exclusive: UnsafeCell<()>;
+}
+
+/// Prepare the `eret` from EL2 to EL1 in AArch64, with all exceptions masked.
+///
+/// # Safety
+///
+/// - The stack EL1 starts with is the boot core stack, whatever EL2 left on it is lost.
+#[inline(always)]
+unsafe fn prepare_el2_to_el1_transition() {
+    // Let EL1 read the counters and use the physical timer.
+    CNTHCTL_EL2.write(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
+
+    // The virtual counter reads the same as the physical one.
+    CNTVOFF_EL2.set(0);
 
-global_asm!(include_str!("boot.s"),CONST_CORE_ID_MASK=const 0b11);
+    // EL1 runs in AArch64.
+    HCR_EL2.write(HCR_EL2::RW::EL1IsAarch64);
 
+    // Fake an exception taken from EL1h, with all exceptions masked, that returns to
+    // `_start_rust_el1`.
+    SPSR_EL2.write(
+        SPSR_EL2::D::Masked
+            + SPSR_EL2::A::Masked
+            + SPSR_EL2::I::Masked
+            + SPSR_EL2::F::Masked
+            + SPSR_EL2::M::EL1h,
+    );
+    ELR_EL2.set(_start_rust_el1 as *const () as u64);
+
+    SP_EL1.set(__boot_core_stack_end_exclusive.get() as u64);
+}
+
+/// `_start` passes the firmware's x0..x3 along unchanged. `reentered` is non-zero if a payload
+/// came back through the re-entry vector.
+///
+/// The firmware starts the loader at EL2, which drops to EL1 here. Payloads are started at EL1
+/// and come back at EL1, so there is nothing left to do then.
 #[no_mangle]
-pub unsafe fn _start_rust() -> !{
-    crate::kernel_init()
-}
\ No newline at end of file
+pub unsafe extern "C" fn _start_rust(x0: u64, x1: u64, x2: u64, x3: u64, reentered: u64) -> ! {
+    match CurrentEL.read_as_enum(CurrentEL::EL) {
+        Some(CurrentEL::EL::Value::EL2) => {
+            prepare_el2_to_el1_transition();
+
+            // The arguments survive the `eret` in x0..x4.
+            asm!(
+                "eret",
+                in("x0") x0,
+                in("x1") x1,
+                in("x2") x2,
+                in("x3") x3,
+                in("x4") reentered,
+                options(noreturn)
+            )
+        }
+        Some(CurrentEL::EL::Value::EL1) => _start_rust_el1(x0, x1, x2, x3, reentered),
+        _ => crate::cpu::wait_forever(),
+    }
+}
+
+unsafe extern "C" fn _start_rust_el1(x0: u64, x1: u64, x2: u64, x3: u64, reentered: u64) -> ! {
+    crate::kernel_init(BootArgs { x0, x1, x2, x3, reentered: reentered != 0 })
+}
@@ -13,6 +13,24 @@
 .section .text._start
 
 _start:
+	// The first words of the binary are fixed: the firmware enters at offset 0, a payload that
+	// wants to go back to the loader at offset 4 (the re-entry vector), and offset 8 holds the
+	// magic the payload passes along.
+	b	.L_cold_start
+	b	.L_warm_start
+.L_reentry_magic:
+	.quad	{CONST_REENTRY_MAGIC}
+
+.L_cold_start:
+	// Keep the arguments the firmware passed in x0..x3 (x0 is the device tree blob) out of the
+	// way. x19..x23 are not touched until the jump to Rust.
+	mov	x19, x0
+	mov	x20, x1
+	mov	x21, x2
+	mov	x22, x3
+	mov	x23, xzr
+
+.L_boot:
 	// Only proceed on the boot core. Park it otherwise.
 	mrs	x0, MPIDR_EL1
 	and	x0, x0, {CONST_CORE_ID_MASK}
@@ -50,9 +68,34 @@ _start:
 	ADR_ABS	x0, __boot_core_stack_end_exclusive
 	mov	sp, x0
 
+	// Hand the firmware's arguments to Rust, and whether a payload came back.
+	mov	x0, x19
+	mov	x1, x20
+	mov	x2, x21
+	mov	x3, x22
+	mov	x4, x23
+
 	// Jump to the relocated Rust code.
-	ADR_ABS	x1, _start_rust
-	br	x1
+	ADR_ABS	x9, _start_rust
+	br	x9
+
+	// A payload came back. Everything but the loader's own memory is gone, so boot again from
+	// scratch: the copy the binary gets relocated from is the relocated binary itself.
+.L_warm_start:
+	// Stray jumps do not count, the payload must pass the magic in x0.
+	ldr	x9, .L_reentry_magic
+	cmp	x0, x9
+	b.ne	.L_parking_loop
+
+	msr	DAIFSet, #0b1111
+
+	// The payload hands back the device tree in x1, or 0.
+	mov	x19, x1
+	mov	x20, xzr
+	mov	x21, xzr
+	mov	x22, xzr
+	mov	x23, #1
+	b	.L_boot
 
 	// Infinitely wait for events (aka "park the core").
 .L_parking_loop:
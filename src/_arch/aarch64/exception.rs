@@ -0,0 +1,284 @@
+//! Exception vectors and the context the handlers get.
+use core::arch::global_asm;
+use core::cell::UnsafeCell;
+use core::fmt;
+use crate::exception;
+use cortex_a::{asm::barrier, registers::*};
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::registers::InMemoryRegister;
+
+global_asm!(include_str!("exception.s"));
+
+// Symbols from exception.s.
+extern "Rust" {
+    static __exception_vector_start: UnsafeCell<()>;
+}
+
+/// ESR_EL1 as saved on exception entry.
+#[repr(transparent)]
+struct EsrEL1(InMemoryRegister<u64, ESR_EL1::Register>);
+
+/// SPSR_EL1 as saved on exception entry.
+#[repr(transparent)]
+struct SpsrEL1(InMemoryRegister<u64, SPSR_EL1::Register>);
+
+/// The registers saved by `CALL_WITH_CONTEXT` in exception.s, in the same order.
+#[repr(C)]
+struct ExceptionContext {
+    /// x0..x29.
+    gpr: [u64; 30],
+
+    /// x30.
+    lr: u64,
+
+    elr_el1: u64,
+
+    spsr_el1: SpsrEL1,
+
+    esr_el1: EsrEL1,
+}
+
+/// Print everything known about the exception and stop.
+fn default_exception_handler(exc: &ExceptionContext) {
+    panic!(
+        "CPU Exception!\n\n\
+        {}",
+        exc
+    );
+}
+
+//------------------------------------------------------------------------------
+// Current, EL0
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+extern "C" fn current_el0_synchronous(_e: &mut ExceptionContext) {
+    panic!("Should not be here. Use of SP_EL0 in EL1 is not supported.")
+}
+
+#[no_mangle]
+extern "C" fn current_el0_irq(_e: &mut ExceptionContext) {
+    panic!("Should not be here. Use of SP_EL0 in EL1 is not supported.")
+}
+
+#[no_mangle]
+extern "C" fn current_el0_fiq(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn current_el0_serror(_e: &mut ExceptionContext) {
+    panic!("Should not be here. Use of SP_EL0 in EL1 is not supported.")
+}
+
+//------------------------------------------------------------------------------
+// Current, ELx
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn current_elx_irq(_e: &mut ExceptionContext) {
+    let token = unsafe { &exception::asynchronous::IRQContext::new() };
+    exception::asynchronous::irq_manager().handle_pending_irqs(token);
+}
+
+#[no_mangle]
+extern "C" fn current_elx_fiq(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn current_elx_serror(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+//------------------------------------------------------------------------------
+// Lower, AArch64
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+extern "C" fn lower_aarch64_synchronous(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch64_irq(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch64_fiq(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch64_serror(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+//------------------------------------------------------------------------------
+// Lower, AArch32
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+extern "C" fn lower_aarch32_synchronous(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch32_irq(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch32_fiq(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch32_serror(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+//------------------------------------------------------------------------------
+// Printing
+//------------------------------------------------------------------------------
+
+impl fmt::Display for EsrEL1 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ESR_EL1::EC::Value::*;
+
+        writeln!(f, "ESR_EL1: {:#010x}", self.0.get())?;
+
+        write!(f, "      Exception Class         (EC) : {:#x}", self.0.read(ESR_EL1::EC))?;
+        let ec_translation = match self.0.read_as_enum(ESR_EL1::EC) {
+            Some(Unknown) => "Unknown reason, e.g. an undefined instruction",
+            Some(TrappedWFIorWFE) => "Trapped WFI or WFE",
+            Some(TrappedFP) => "Trapped floating point or SIMD access",
+            Some(IllegalExecutionState) => "Illegal execution state",
+            Some(SVC64) => "SVC instruction",
+            Some(HVC64) => "HVC instruction",
+            Some(SMC64) => "SMC instruction",
+            Some(TrappedMsrMrs) => "Trapped MSR, MRS or system instruction",
+            Some(InstrAbortLowerEL) => "Instruction Abort, lower EL",
+            Some(InstrAbortCurrentEL) => "Instruction Abort, current EL",
+            Some(PCAlignmentFault) => "PC alignment fault",
+            Some(DataAbortLowerEL) => "Data Abort, lower EL",
+            Some(DataAbortCurrentEL) => "Data Abort, current EL",
+            Some(SPAlignmentFault) => "SP alignment fault",
+            Some(SError) => "SError interrupt",
+            Some(BreakpointLowerEL) => "Breakpoint, lower EL",
+            Some(BreakpointCurrentEL) => "Breakpoint, current EL",
+            Some(WatchpointLowerEL) => "Watchpoint, lower EL",
+            Some(WatchpointCurrentEL) => "Watchpoint, current EL",
+            Some(Brk64) => "BRK instruction",
+            _ => "N/A",
+        };
+        writeln!(f, " - {}", ec_translation)?;
+
+        write!(f, "      Instr Specific Syndrome (ISS): {:#x}", self.0.read(ESR_EL1::ISS))
+    }
+}
+
+impl EsrEL1 {
+    /// Whether FAR_EL1 holds the faulting address for this exception.
+    fn far_valid(&self) -> bool {
+        use ESR_EL1::EC::Value::*;
+
+        matches!(
+            self.0.read_as_enum(ESR_EL1::EC),
+            Some(InstrAbortLowerEL)
+                | Some(InstrAbortCurrentEL)
+                | Some(PCAlignmentFault)
+                | Some(DataAbortLowerEL)
+                | Some(DataAbortCurrentEL)
+                | Some(WatchpointLowerEL)
+                | Some(WatchpointCurrentEL)
+        )
+    }
+}
+
+impl fmt::Display for SpsrEL1 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "SPSR_EL1: {:#010x}", self.0.get())?;
+
+        let to_flag_str = |x| -> _ {
+            if x {
+                "Set"
+            } else {
+                "Not set"
+            }
+        };
+
+        writeln!(f, "      Flags:")?;
+        writeln!(f, "            Negative (N): {}", to_flag_str(self.0.is_set(SPSR_EL1::N)))?;
+        writeln!(f, "            Zero     (Z): {}", to_flag_str(self.0.is_set(SPSR_EL1::Z)))?;
+        writeln!(f, "            Carry    (C): {}", to_flag_str(self.0.is_set(SPSR_EL1::C)))?;
+        writeln!(f, "            Overflow (V): {}", to_flag_str(self.0.is_set(SPSR_EL1::V)))?;
+
+        let to_mask_str = |x| -> _ {
+            if x {
+                "Masked"
+            } else {
+                "Unmasked"
+            }
+        };
+
+        writeln!(f, "      Exception handling state:")?;
+        writeln!(f, "            Debug  (D): {}", to_mask_str(self.0.is_set(SPSR_EL1::D)))?;
+        writeln!(f, "            SError (A): {}", to_mask_str(self.0.is_set(SPSR_EL1::A)))?;
+        writeln!(f, "            IRQ    (I): {}", to_mask_str(self.0.is_set(SPSR_EL1::I)))?;
+        writeln!(f, "            FIQ    (F): {}", to_mask_str(self.0.is_set(SPSR_EL1::F)))?;
+
+        write!(
+            f,
+            "      Illegal Execution State (IL): {}",
+            to_flag_str(self.0.is_set(SPSR_EL1::IL))
+        )
+    }
+}
+
+impl fmt::Display for ExceptionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.esr_el1)?;
+
+        if self.esr_el1.far_valid() {
+            writeln!(f, "FAR_EL1: {:#018x}", FAR_EL1.get())?;
+        }
+
+        writeln!(f, "{}", self.spsr_el1)?;
+        writeln!(f, "ELR_EL1: {:#018x}", self.elr_el1)?;
+        writeln!(f)?;
+        writeln!(f, "General purpose register:")?;
+
+        let alternating = |x| -> _ {
+            if x % 2 == 0 {
+                "   "
+            } else {
+                "\n"
+            }
+        };
+
+        // Print two registers per line.
+        for (i, reg) in self.gpr.iter().enumerate() {
+            write!(f, "      x{: <2}: {: >#018x}{}", i, reg, alternating(i))?;
+        }
+        write!(f, "      lr : {:#018x}", self.lr)
+    }
+}
+
+/// Point VBAR_EL1 at the vector table.
+///
+/// # Safety
+///
+/// - Changes the hardware state of the executing core.
+pub unsafe fn handling_init() {
+    VBAR_EL1.set(__exception_vector_start.get() as u64);
+
+    // Force VBAR update to complete before next instruction.
+    barrier::isb(barrier::SY);
+}
@@ -0,0 +1,141 @@
+//! Identity mapping with 2 MiB blocks and the 4 KiB translation granule.
+use core::arch::{asm, global_asm};
+use core::ptr::addr_of_mut;
+use crate::bsp;
+use cortex_a::{asm::barrier, registers::*};
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+
+global_asm!(include_str!("mmu.s"));
+
+// Symbols from mmu.s.
+extern "C" {
+    fn __dcache_invalidate_all();
+    fn __mmu_off_clean_dcache();
+}
+
+/// The mapped part of the address space, which is 2^(64 - T0SZ) bytes.
+const ADDRESS_SPACE_SIZE: usize = 1 << 32;
+const T0SZ: u64 = 32;
+
+/// With T0SZ = 32, a level 1 entry spans 1 GiB and a level 2 entry 2 MiB.
+const L1_SHIFT: usize = 30;
+const L2_SHIFT: usize = 21;
+const ENTRIES_PER_TABLE: usize = 512;
+const L2_TABLES: usize = ADDRESS_SPACE_SIZE >> L1_SHIFT;
+
+// Descriptor bits.
+const VALID: u64 = 1 << 0;
+/// At level 1: points to a level 2 table instead of mapping a block.
+const TABLE: u64 = 1 << 1;
+const ATTR_INDEX_SHIFT: u64 = 2;
+const INNER_SHAREABLE: u64 = 0b11 << 8;
+const OUTER_SHAREABLE: u64 = 0b10 << 8;
+/// Access flag. Without it, the first access to the block faults.
+const ACCESSED: u64 = 1 << 10;
+const PRIVILEGED_EXECUTE_NEVER: u64 = 1 << 53;
+const UNPRIVILEGED_EXECUTE_NEVER: u64 = 1 << 54;
+
+/// Indices into MAIR_EL1.
+const MAIR_DEVICE: u64 = 0;
+const MAIR_NORMAL: u64 = 1;
+
+#[repr(C, align(4096))]
+struct Table([u64; ENTRIES_PER_TABLE]);
+
+const EMPTY_TABLE: Table = Table([0; ENTRIES_PER_TABLE]);
+
+struct TranslationTables {
+    l1: Table,
+    l2: [Table; L2_TABLES],
+}
+
+/// In `.bss`, so the tables are built anew on every boot, re-entries included.
+static mut TABLES: TranslationTables = TranslationTables {
+    l1: EMPTY_TABLE,
+    l2: [EMPTY_TABLE; L2_TABLES],
+};
+
+/// Block descriptor mapping the 2 MiB at `addr` to itself.
+fn block_descriptor(addr: usize) -> u64 {
+    let attributes = if addr < bsp::memory::device_memory_start() {
+        (MAIR_NORMAL << ATTR_INDEX_SHIFT) | INNER_SHAREABLE
+    } else {
+        (MAIR_DEVICE << ATTR_INDEX_SHIFT)
+            | OUTER_SHAREABLE
+            | PRIVILEGED_EXECUTE_NEVER
+    };
+
+    addr as u64 | attributes | ACCESSED | UNPRIVILEGED_EXECUTE_NEVER | VALID
+}
+
+unsafe fn populate_tables() -> u64 {
+    let tables = &mut *addr_of_mut!(TABLES);
+
+    for (i, l2) in tables.l2.iter_mut().enumerate() {
+        for (j, entry) in l2.0.iter_mut().enumerate() {
+            *entry = block_descriptor((i << L1_SHIFT) | (j << L2_SHIFT));
+        }
+        tables.l1.0[i] = l2.0.as_ptr() as u64 | TABLE | VALID;
+    }
+
+    tables.l1.0.as_ptr() as u64
+}
+
+/// Map the address space 1:1 and turn the MMU and the caches on.
+///
+/// # Safety
+///
+/// - Changes the attributes of all memory accesses. Must be called once per boot, before
+///   anything relies on the caches, and with the MMU off.
+pub unsafe fn enable_mmu_and_caching() -> Result<(), &'static str> {
+    if SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable) {
+        return Err("MMU already enabled");
+    }
+
+    if !ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran4::Supported) {
+        return Err("4 KiB translation granule not supported");
+    }
+
+    let l1 = populate_tables();
+
+    MAIR_EL1.write(
+        MAIR_EL1::Attr0_Device::nonGathering_nonReordering_EarlyWriteAck
+            + MAIR_EL1::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc
+            + MAIR_EL1::Attr1_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc,
+    );
+    TTBR0_EL1.set_baddr(l1);
+    TCR_EL1.write(
+        TCR_EL1::IPS::Bits_32
+            + TCR_EL1::TG0::KiB_4
+            + TCR_EL1::SH0::Inner
+            + TCR_EL1::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + TCR_EL1::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + TCR_EL1::EPD0::EnableTTBR0Walks
+            + TCR_EL1::A1::TTBR0
+            + TCR_EL1::T0SZ.val(T0SZ)
+            + TCR_EL1::EPD1::DisableTTBR1Walks,
+    );
+    barrier::dsb(barrier::ISHST);
+
+    // The loader and a payload that came back wrote memory with the caches off, around whatever
+    // lines the caches still hold from before.
+    __dcache_invalidate_all();
+    asm!("tlbi vmalle1", "ic iallu", options(nostack));
+    barrier::dsb(barrier::NSH);
+    barrier::isb(barrier::SY);
+
+    SCTLR_EL1.modify(SCTLR_EL1::M::Enable + SCTLR_EL1::C::Cacheable + SCTLR_EL1::I::Cacheable);
+    barrier::isb(barrier::SY);
+
+    Ok(())
+}
+
+/// Write the data cache back and turn the MMU and the caches off, the state payloads are started
+/// in.
+///
+/// # Safety
+///
+/// - Nothing that relies on the caches, like `SpinLock`, may be used afterwards.
+pub unsafe fn disable_mmu_and_caching() {
+    __mmu_off_clean_dcache();
+}
@@ -0,0 +1,72 @@
+// Run `dc \op` on every line of every data or unified cache up to the level of coherence, by set
+// and way. Uses x0..x12 only, no memory.
+.macro DCACHE_BY_SET_WAY op
+	mrs	x0,  CLIDR_EL1
+	ubfx	w3,  w0,  #24, #3		// Level of coherence.
+	cbz	w3,  5f
+	mov	w10, #0				// Cache level, from 0.
+1:
+	add	w2,  w10, w10, lsl #1		// Cache type of this level, in bits 3 * level.
+	lsr	w1,  w0,  w2
+	and	w1,  w1,  #7
+	cmp	w1,  #2
+	b.lt	4f				// No data or unified cache.
+	lsl	w1,  w10, #1
+	msr	CSSELR_EL1, x1
+	isb
+	mrs	x1,  CCSIDR_EL1
+	and	w2,  w1,  #7
+	add	w2,  w2,  #4			// Log2 of the line length.
+	ubfx	w4,  w1,  #3,  #10		// Number of ways - 1.
+	clz	w5,  w4				// Position of the way in the operand.
+	mov	w9,  w4				// Way counter.
+2:
+	ubfx	w7,  w1,  #13, #15		// Set counter, number of sets - 1.
+3:
+	lsl	w11, w10, #1
+	lsl	w12, w9,  w5
+	orr	w11, w11, w12
+	lsl	w12, w7,  w2
+	orr	w11, w11, w12
+	dc	\op, x11
+	subs	w7,  w7,  #1
+	b.ge	3b
+	subs	w9,  w9,  #1
+	b.ge	2b
+4:
+	add	w10, w10, #1
+	cmp	w10, w3
+	b.lt	1b
+5:
+	dsb	sy
+	isb
+.endm
+
+// Throw away whatever the data caches hold. Only safe with the data cache off, when nothing in
+// them can be dirty.
+.section .text.__dcache_invalidate_all
+.global __dcache_invalidate_all
+__dcache_invalidate_all:
+	DCACHE_BY_SET_WAY isw
+	ret
+
+// Write the data caches back and turn the MMU and the caches off. Nothing touches memory between
+// the two, so no dirty line can be left behind.
+.section .text.__mmu_off_clean_dcache
+.global __mmu_off_clean_dcache
+__mmu_off_clean_dcache:
+	DCACHE_BY_SET_WAY cisw
+
+	mrs	x0,  SCTLR_EL1
+	mov	x1,  #(1 << 0 | 1 << 2 | 1 << 12)	// M, C and I.
+	bic	x0,  x0,  x1
+	msr	SCTLR_EL1, x0
+	isb
+
+	// The payload was written through the data cache, so the instruction cache may hold stale
+	// lines for it.
+	ic	iallu
+	tlbi	vmalle1
+	dsb	nsh
+	isb
+	ret
@@ -0,0 +1,37 @@
+//! ARM generic timer.
+use core::time::Duration;
+use cortex_a::{asm::barrier, registers::*};
+use tock_registers::interfaces::Readable;
+use crate::time;
+
+const NANOSEC_PER_SEC: u128 = 1_000_000_000;
+
+/// The physical counter of the generic timer. Runs from reset at the frequency the firmware put in
+/// CNTFRQ_EL0, so it needs no driver.
+pub struct GenericTimer;
+
+pub static GENERIC_TIMER: GenericTimer = GenericTimer;
+
+#[inline(always)]
+fn read_cntpct() -> u64 {
+    // Prevent that the counter is read ahead of time due to out-of-order execution.
+    barrier::isb(barrier::SY);
+    CNTPCT_EL0.get()
+}
+
+fn ticks_to_duration(ticks: u64) -> Duration {
+    let frequency = u128::from(CNTFRQ_EL0.get());
+
+    Duration::from_nanos((u128::from(ticks) * NANOSEC_PER_SEC / frequency) as u64)
+}
+
+impl time::interface::TimeManager for GenericTimer {
+    fn resolution(&self) -> Duration {
+        ticks_to_duration(1)
+    }
+
+    /// Roughly the time since power on.
+    fn uptime(&self) -> Duration {
+        ticks_to_duration(read_cntpct())
+    }
+}
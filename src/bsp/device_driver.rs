@@ -1,6 +1,11 @@
+#[cfg(feature = "bsp-rpi-4")]
+mod arm;
 #[cfg(any(feature = "bsp-rpi-4", feature = "bsp-rpi-3"))]
 mod bcm;
 mod common;
 
+#[cfg(feature = "bsp-rpi-4")]
+pub use arm::*;
 #[cfg(any(feature = "bsp-rpi-4", feature = "bsp-rpi-3"))]
-pub use bcm::*;
\ No newline at end of file
+pub use bcm::*;
+pub use common::{DataBits, LineConfig, Parity, StopBits, DEFAULT_BAUD_RATE};
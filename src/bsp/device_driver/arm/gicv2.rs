@@ -0,0 +1,171 @@
+//! GICv2 Driver - ARM Generic Interrupt Controller v2, as found in the GIC-400 of the BCM2711.
+//!
+//! Interrupt IDs are split into three ranges:
+//!
+//! - 0..=15: software generated interrupts (SGIs), raised by a core through `GICD_SGIR`.
+//! - 16..=31: private peripheral interrupts (PPIs), e.g. the core's generic timer.
+//! - 32..: shared peripheral interrupts (SPIs), the interrupts of the SoC's peripherals.
+//!
+//! The distributor (GICD) prioritises the interrupts and forwards them to the CPU interfaces
+//! (GICC) of the cores they target, where they are acknowledged and completed. Only the boot
+//! core's CPU interface is used.
+
+mod gicc;
+mod gicd;
+
+use crate::driver::interface::DeviceDriver;
+use crate::exception::asynchronous::{interface, BoundedUsize, IRQContext, IRQHandlerDescriptor};
+use crate::synchronization::interface::ReadWriteEx;
+use crate::synchronization::ReadWriteExclusive;
+use crate::println;
+
+/// Used for the associated type of trait [`interface::IRQManager`].
+pub type IRQNumber = BoundedUsize<{ GICv2::MAX_IRQ_NUMBER }>;
+
+type HandlerTable = [Option<IRQHandlerDescriptor<IRQNumber>>; GICv2::NUM_IRQS];
+
+/// Representation of the GIC.
+pub struct GICv2 {
+    /// The Distributor.
+    gicd: gicd::GICD,
+
+    /// The CPU Interface.
+    gicc: gicc::GICC,
+
+    /// Stores registered IRQ handlers.
+    handler_table: ReadWriteExclusive<HandlerTable>,
+}
+
+impl GICv2 {
+    /// IDs above are special, e.g. 1023 for "nothing pending".
+    const MAX_ARCH_IRQ_NUMBER: usize = 1019;
+
+    /// The BCM2711 raises no ID above 300, which keeps the handler table small.
+    const MAX_IRQ_NUMBER: usize = 300;
+    const NUM_IRQS: usize = Self::MAX_IRQ_NUMBER + 1;
+
+    /// The first PPI and the first SPI.
+    pub const FIRST_PPI: usize = 16;
+    pub const FIRST_SPI: usize = 32;
+
+    /// Priority given to every interrupt at init. Lower values win.
+    pub const DEFAULT_PRIORITY: u8 = 0xA0;
+
+    pub const COMPATIBLE: &'static str = "GICv2 (ARM Generic Interrupt Controller v2)";
+
+    pub const unsafe fn new(gicd_mmio_start_addr: usize, gicc_mmio_start_addr: usize) -> Self {
+        Self {
+            gicd: gicd::GICD::new(gicd_mmio_start_addr),
+            gicc: gicc::GICC::new(gicc_mmio_start_addr),
+            handler_table: ReadWriteExclusive::new([None; Self::NUM_IRQS]),
+        }
+    }
+}
+
+impl DeviceDriver for GICv2 {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        // Start from nothing enabled, whatever a payload that came back left behind. The table
+        // is in `.data` and survives that as well.
+        self.gicd.disable_all();
+        self.handler_table.write(|table| *table = [None; Self::NUM_IRQS]);
+
+        self.gicd.boot_core_init(Self::NUM_IRQS, Self::DEFAULT_PRIORITY);
+
+        self.gicc.priority_accept_all();
+        self.gicc.enable();
+
+        Ok(())
+    }
+}
+
+impl interface::IRQManager for GICv2 {
+    type IRQNumberType = IRQNumber;
+
+    fn register_handler(
+        &self,
+        irq_handler_descriptor: IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        self.handler_table.write(|table| {
+            let slot = &mut table[irq_handler_descriptor.number().get()];
+            if slot.is_some() {
+                return Err("IRQ handler already registered");
+            }
+
+            *slot = Some(irq_handler_descriptor);
+            Ok(())
+        })
+    }
+
+    fn enable(&self, irq_number: &Self::IRQNumberType) {
+        self.gicd.enable(irq_number.get());
+    }
+
+    fn disable_all(&self) {
+        self.gicd.disable_all();
+    }
+
+    /// Set the priority of `irq`. Lower values win; the GIC-400 ignores the low bits.
+    fn set_priority(&self, irq: &IRQNumber, priority: u8) -> Result<(), &'static str> {
+        self.gicd.set_priority(irq.get(), priority);
+        Ok(())
+    }
+
+    /// Route the SPI `irq` to the cores in `cpu_mask`, bit n standing for core n. SGIs and PPIs
+    /// always go to the core they belong to.
+    fn set_target(&self, irq: &IRQNumber, cpu_mask: u8) -> Result<(), &'static str> {
+        if irq.get() < Self::FIRST_SPI {
+            return Err("only SPIs can be routed");
+        }
+
+        self.gicd.set_target(irq.get(), cpu_mask);
+        Ok(())
+    }
+
+    /// Raise the SGI `irq` on the cores in `cpu_mask`.
+    fn send_sgi(&self, irq: &IRQNumber, cpu_mask: u8) -> Result<(), &'static str> {
+        if irq.get() >= Self::FIRST_PPI {
+            return Err("not an SGI");
+        }
+
+        self.gicd.send_sgi(irq.get(), cpu_mask);
+        Ok(())
+    }
+
+    fn handle_pending_irqs<'irq_context>(&'irq_context self, ic: &IRQContext<'irq_context>) {
+        loop {
+            // Reading the acknowledge register moves the interrupt from pending to active.
+            let ack = self.gicc.acknowledge(ic);
+            let irq = gicc::GICC::interrupt_id(ack);
+
+            // Spurious: nothing (left) pending.
+            if irq > Self::MAX_ARCH_IRQ_NUMBER {
+                break;
+            }
+
+            // The handler runs without the lock held.
+            match self.handler_table.read(|table| table.get(irq).copied().flatten()) {
+                None => panic!("No handler registered for IRQ {}", irq),
+                Some(descriptor) => {
+                    // Call the IRQ handler. Panics on failure.
+                    descriptor.handler().handle().expect("Error handling IRQ");
+                }
+            }
+
+            self.gicc.end_of_interrupt(ack, ic);
+        }
+    }
+
+    fn print_handler(&self) {
+        self.handler_table.read(|table| {
+            for (i, descriptor) in table.iter().enumerate() {
+                if let Some(descriptor) = descriptor {
+                    println!("      {}. {}", i, descriptor.name());
+                }
+            }
+        })
+    }
+}
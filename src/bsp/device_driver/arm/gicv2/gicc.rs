@@ -0,0 +1,105 @@
+//! GICC Driver - GIC CPU interface.
+//!
+//! The executing core's view of the interrupts the distributor forwards to it.
+
+use tock_registers::{register_bitfields, register_structs, registers::ReadOnly, registers::ReadWrite, registers::WriteOnly};
+use tock_registers::interfaces::{Readable, Writeable};
+use crate::bsp::device_driver::common::MMIODerefWrapper;
+use crate::exception::asynchronous::IRQContext;
+use crate::synchronization::interface::Mutex;
+use crate::synchronization::IRQSafeNullLock;
+
+register_bitfields! {
+    u32,
+    /// CPU Interface Control Register
+    CTLR [
+        Enable OFFSET(0) NUMBITS(1) []
+    ],
+    /// Interrupt Priority Mask Register
+    PMR [
+        /// Only interrupts with a lower (higher priority) value are signalled to the core.
+        Priority OFFSET(0) NUMBITS(8) []
+    ],
+    /// Interrupt Acknowledge Register
+    IAR [
+        /// For SGIs, the core that raised it.
+        CPUID OFFSET(10) NUMBITS(3) [],
+        InterruptID OFFSET(0) NUMBITS(10) []
+    ],
+    /// End of Interrupt Register. Takes the value read from IAR.
+    EOIR [
+        EOIINTID OFFSET(0) NUMBITS(13) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x000 => CTLR: ReadWrite<u32, CTLR::Register>),
+        (0x004 => PMR: ReadWrite<u32, PMR::Register>),
+        (0x008 => _reserved1),
+        (0x00c => IAR: ReadOnly<u32, IAR::Register>),
+        (0x010 => EOIR: WriteOnly<u32, EOIR::Register>),
+        (0x014 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+struct GICCInner {
+    registers: Registers,
+}
+
+/// Representation of the GIC CPU interface. Its registers are banked, each core sees its own.
+pub struct GICC {
+    inner: IRQSafeNullLock<GICCInner>,
+}
+
+impl GICCInner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+        }
+    }
+}
+
+impl GICC {
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(GICCInner::new(mmio_start_addr)),
+        }
+    }
+
+    /// Let interrupts of any priority through to the core.
+    pub fn priority_accept_all(&self) {
+        // The GIC-400 implements fewer than 8 priority bits, the others are RAZ/WI.
+        self.inner.lock(|inner| inner.registers.PMR.write(PMR::Priority.val(255)))
+    }
+
+    /// Signal forwarded interrupts to the core.
+    pub fn enable(&self) {
+        self.inner.lock(|inner| inner.registers.CTLR.write(CTLR::Enable::SET))
+    }
+
+    /// Acknowledge the highest priority pending interrupt and return the raw IAR value, which
+    /// must be handed to `end_of_interrupt` once it is handled.
+    ///
+    /// Can only be called from IRQ context, which is ensured by taking an `IRQContext` token.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn acknowledge<'irq_context>(&self, _ic: &IRQContext<'irq_context>) -> u32 {
+        self.inner.lock(|inner| inner.registers.IAR.get())
+    }
+
+    /// The interrupt ID in an IAR value. IDs above 1019 mean that nothing is pending.
+    pub fn interrupt_id(ack: u32) -> usize {
+        (ack & IAR::InterruptID.mask) as usize
+    }
+
+    /// Complete the interrupt acknowledged with `ack`.
+    ///
+    /// Can only be called from IRQ context, which is ensured by taking an `IRQContext` token.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn end_of_interrupt<'irq_context>(&self, ack: u32, _ic: &IRQContext<'irq_context>) {
+        self.inner.lock(|inner| inner.registers.EOIR.set(ack))
+    }
+}
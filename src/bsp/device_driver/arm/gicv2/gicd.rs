@@ -0,0 +1,166 @@
+//! GICD Driver - GIC Distributor.
+//!
+//! Enables interrupts, sets their priority and routes the SPIs to the cores. Registers with a
+//! byte per interrupt (priority, targets) are accessed as words of four interrupts.
+
+use tock_registers::{register_bitfields, register_structs, registers::ReadOnly, registers::ReadWrite, registers::WriteOnly};
+use tock_registers::interfaces::{Readable, Writeable};
+use super::GICv2;
+use crate::bsp::device_driver::common::MMIODerefWrapper;
+use crate::synchronization::interface::Mutex;
+use crate::synchronization::IRQSafeNullLock;
+
+register_bitfields! {
+    u32,
+    /// Distributor Control Register
+    CTLR [
+        Enable OFFSET(0) NUMBITS(1) []
+    ],
+    /// Interrupt Controller Type Register
+    TYPER [
+        /// The number of implemented interrupt IDs is 32 * (ITLinesNumber + 1).
+        ITLinesNumber OFFSET(0) NUMBITS(5) []
+    ],
+    /// Software Generated Interrupt Register
+    SGIR [
+        /// 0b00: the cores in CPUTargetList.
+        TargetListFilter OFFSET(24) NUMBITS(2) [
+            CPUTargetList = 0b00
+        ],
+        CPUTargetList OFFSET(16) NUMBITS(8) [],
+        SGIINTID OFFSET(0) NUMBITS(4) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x000 => CTLR: ReadWrite<u32, CTLR::Register>),
+        (0x004 => TYPER: ReadOnly<u32, TYPER::Register>),
+        (0x008 => _reserved1),
+        /// One bit per interrupt. Writing 1 enables, reading returns the enabled ones.
+        (0x100 => ISENABLER: [ReadWrite<u32>; 32]),
+        /// One bit per interrupt. Writing 1 disables.
+        (0x180 => ICENABLER: [ReadWrite<u32>; 32]),
+        (0x200 => _reserved2),
+        /// One byte per interrupt.
+        (0x400 => IPRIORITYR: [ReadWrite<u32>; 255]),
+        (0x7fc => _reserved3),
+        /// One byte per interrupt, bit n standing for core n. Read-only for SGIs and PPIs, where
+        /// it returns the mask of the reading core.
+        (0x800 => ITARGETSR: [ReadWrite<u32>; 255]),
+        (0xbfc => _reserved4),
+        (0xf00 => SGIR: WriteOnly<u32, SGIR::Register>),
+        (0xf04 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+struct GICDInner {
+    registers: Registers,
+}
+
+/// Representation of the GIC Distributor.
+pub struct GICD {
+    inner: IRQSafeNullLock<GICDInner>,
+}
+
+/// A byte value repeated in all four bytes of a word.
+const fn replicate(byte: u8) -> u32 {
+    u32::from_ne_bytes([byte; 4])
+}
+
+impl GICDInner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+        }
+    }
+
+    /// The number of interrupt IDs the distributor implements.
+    fn num_irqs(&self) -> usize {
+        let it_lines_number = self.registers.TYPER.read(TYPER::ITLinesNumber) as usize;
+
+        // 1020..=1023 are special IDs, not interrupts.
+        usize::min(32 * (it_lines_number + 1), 1020)
+    }
+
+    /// The ITARGETSR mask of the executing core.
+    fn local_gic_target_mask(&self) -> u8 {
+        // The targets of SGI 0 are banked and always name the reading core.
+        self.registers.ITARGETSR[0].get() as u8
+    }
+
+    /// Replace byte `irq % 4` of `register`.
+    fn set_byte(register: &ReadWrite<u32>, irq: usize, value: u8) {
+        let shift = (irq % 4) * 8;
+        let word = register.get() & !(0xff << shift) | u32::from(value) << shift;
+
+        register.set(word);
+    }
+}
+
+impl GICD {
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(GICDInner::new(mmio_start_addr)),
+        }
+    }
+
+    /// Give the first `num_irqs` interrupts `priority`, route all SPIs to the executing core and
+    /// enable forwarding.
+    pub fn boot_core_init(&self, num_irqs: usize, priority: u8) {
+        self.inner.lock(|inner| {
+            let num_irqs = usize::min(num_irqs, inner.num_irqs());
+
+            for register in &inner.registers.IPRIORITYR[..num_irqs.div_ceil(4)] {
+                register.set(replicate(priority));
+            }
+
+            let mask = replicate(inner.local_gic_target_mask());
+            for register in &inner.registers.ITARGETSR[GICv2::FIRST_SPI / 4..num_irqs.div_ceil(4)] {
+                register.set(mask);
+            }
+
+            inner.registers.CTLR.write(CTLR::Enable::SET);
+        })
+    }
+
+    pub fn enable(&self, irq: usize) {
+        self.inner.lock(|inner| inner.registers.ISENABLER[irq / 32].set(1 << (irq % 32)))
+    }
+
+    /// Disable every implemented interrupt. SGIs cannot be disabled on the GIC-400 and stay as
+    /// they are.
+    pub fn disable_all(&self) {
+        self.inner.lock(|inner| {
+            let num_irqs = inner.num_irqs();
+            for register in &inner.registers.ICENABLER[..num_irqs.div_ceil(32)] {
+                register.set(u32::MAX);
+            }
+        })
+    }
+
+    pub fn set_priority(&self, irq: usize, priority: u8) {
+        self.inner.lock(|inner| {
+            GICDInner::set_byte(&inner.registers.IPRIORITYR[irq / 4], irq, priority)
+        })
+    }
+
+    pub fn set_target(&self, irq: usize, cpu_mask: u8) {
+        self.inner.lock(|inner| {
+            GICDInner::set_byte(&inner.registers.ITARGETSR[irq / 4], irq, cpu_mask)
+        })
+    }
+
+    pub fn send_sgi(&self, irq: usize, cpu_mask: u8) {
+        self.inner.lock(|inner| {
+            inner.registers.SGIR.write(
+                SGIR::TargetListFilter::CPUTargetList
+                    + SGIR::CPUTargetList.val(u32::from(cpu_mask))
+                    + SGIR::SGIINTID.val(irq as u32),
+            )
+        })
+    }
+}
@@ -1,5 +1,19 @@
+#[cfg(not(feature = "mini-uart"))]
 mod bcm2xxx_p1011_uart;
 mod bcm2xxx_gpio;
+#[cfg(feature = "mini-uart")]
+mod bcm2xxx_mini_uart;
+#[cfg(feature = "system-timer")]
+mod bcm2xxx_system_timer;
+#[cfg(feature = "bsp-rpi-3")]
+mod bcm2xxx_interrupt_controller;
 
+#[cfg(not(feature = "mini-uart"))]
 pub use bcm2xxx_p1011_uart::*;
-pub use bcm2xxx_gpio::*;
\ No newline at end of file
+pub use bcm2xxx_gpio::*;
+#[cfg(feature = "mini-uart")]
+pub use bcm2xxx_mini_uart::*;
+#[cfg(feature = "system-timer")]
+pub use bcm2xxx_system_timer::*;
+#[cfg(feature = "bsp-rpi-3")]
+pub use bcm2xxx_interrupt_controller::*;
\ No newline at end of file
@@ -1,24 +1,22 @@
-use tock_registers::{register_bitfields, register_structs, registers::ReadWrite};
-use tock_registers::interfaces::Writeable;
+use core::marker::PhantomData;
+use tock_registers::{
+    register_bitfields, register_structs,
+    registers::{ReadOnly, ReadWrite, WriteOnly},
+};
+use tock_registers::interfaces::{Readable, Writeable};
 use crate::bsp::device_driver::common::MMIODerefWrapper;
-use crate::synchronization::NullLock;
+#[cfg(feature = "bsp-rpi-3")]
+use core::time::Duration;
+#[cfg(feature = "bsp-rpi-3")]
+use crate::time;
+use crate::synchronization::SpinLock;
 use crate::driver::interface::DeviceDriver;
+use crate::exception::asynchronous::{irq_manager, IRQHandlerDescriptor, IRQNumber};
+use crate::exception;
 use crate::synchronization::interface::Mutex;
 
 register_bitfields! {
     u32,
-    GPFSEL1 [
-        FSEL15 OFFSET(15) NUMBITS(3) [
-            INPUT = 0b000,
-            OUTPUT = 0b001,
-            ALTFUN = 0b100,
-        ],
-        FSEL14 OFFSET(12) NUMBITS(3) [
-           INPUT = 0b000,
-           OUTPUT = 0b001,
-           ALTFUN = 0b100,
-        ]
-    ],
     /// bcm2837 only
     /// The GPIO Pull-up/down Register controls the actuation of the internal pull-up/down control line to ALL the GPIO pins.
     /// This register must be used in conjunction with the 2 GPPUDCLKn registers.
@@ -38,96 +36,313 @@ register_bitfields! {
             PULLUP = 0b10,
             RESERVED = 0b11,
         ]
-    ],
-    /// bcm2837 only
-    GPPUDCLK0 [
-        PUDCLK15 OFFSET(15) NUMBITS(1) [
-            NoEffect = 0b0,
-            AssertClock = 0b1,
-        ],
-        PUDCLK14 OFFSET(14) NUMBITS(1) [
-            NoEffect = 0b0,
-            AssertClock = 0b1,
-        ]
-    ],
-    /// bcm2711 only
-    GPIO_PUP_PDN_CNTRL_REG0 [
-        GPIO_PUP_PDN_CNTRL15 OFFSET(30) NUMBITS(2) [
-            NoRegister = 0b00,
-            PullUp = 0b01,
-        ],
-        GPIO_PUP_PDN_CNTRL14 OFFSET(28) NUMBITS(2) [
-            NoRegister = 0b00,
-            PullUp = 0b01,
-        ],
     ]
 }
 
 register_structs! {
     #[allow(non_snake_case)]
     pub RegisterBlock {
-        (0x00 => _reserved1),
-        (0x04 => GPFSEL1: ReadWrite<u32, GPFSEL1::Register>),
-        (0x08 => _reserved2),
+        /// Function select, 3 bits per pin, 10 pins per register.
+        (0x00 => GPFSEL: [ReadWrite<u32>; 6]),
+        (0x18 => _reserved1),
+        /// Output set, 1 bit per pin.
+        (0x1c => GPSET: [WriteOnly<u32>; 2]),
+        (0x24 => _reserved2),
+        /// Output clear, 1 bit per pin.
+        (0x28 => GPCLR: [WriteOnly<u32>; 2]),
+        (0x30 => _reserved3),
+        /// Pin level, 1 bit per pin.
+        (0x34 => GPLEV: [ReadOnly<u32>; 2]),
+        (0x3c => _reserved4),
+        /// Event detect status, 1 bit per pin. Write 1 to clear.
+        (0x40 => GPEDS: [ReadWrite<u32>; 2]),
+        (0x48 => _reserved5),
+        /// Rising edge detect enable, synchronised to the system clock.
+        (0x4c => GPREN: [ReadWrite<u32>; 2]),
+        (0x54 => _reserved6),
+        /// Falling edge detect enable, synchronised to the system clock.
+        (0x58 => GPFEN: [ReadWrite<u32>; 2]),
+        (0x60 => _reserved7),
+        /// High level detect enable.
+        (0x64 => GPHEN: [ReadWrite<u32>; 2]),
+        (0x6c => _reserved8),
+        /// Low level detect enable.
+        (0x70 => GPLEN: [ReadWrite<u32>; 2]),
+        (0x78 => _reserved9),
+        /// Asynchronous rising edge detect enable, for pulses shorter than a clock cycle.
+        (0x7c => GPAREN: [ReadWrite<u32>; 2]),
+        (0x84 => _reserved10),
+        /// Asynchronous falling edge detect enable.
+        (0x88 => GPAFEN: [ReadWrite<u32>; 2]),
+        (0x90 => _reserved11),
         (0x94 => GPPUD: ReadWrite<u32, GPPUD::Register>),
-        (0x98 => GPPUDCLK0: ReadWrite<u32, GPPUDCLK0::Register>),
-        (0x9c => _reserved3),
-        (0xe4 => GPIO_PUP_PDN_CNTRL_REG0: ReadWrite<u32, GPIO_PUP_PDN_CNTRL_REG0::Register>),
-        (0xe8 => @END),
+        /// bcm2837 only. Latches `GPPUD` into the pins whose bit is set.
+        (0x98 => GPPUDCLK: [ReadWrite<u32>; 2]),
+        (0xa0 => _reserved12),
+        /// bcm2711 only. Pull configuration, 2 bits per pin, 16 pins per register.
+        (0xe4 => GPIO_PUP_PDN_CNTRL: [ReadWrite<u32>; 4]),
+        (0xf4 => @END),
     }
 }
 
+/// Number of GPIO pins.
+#[cfg(feature = "bsp-rpi-3")]
+pub const NUM_PINS: u8 = 54;
+
+/// Number of GPIO pins.
+#[cfg(feature = "bsp-rpi-4")]
+pub const NUM_PINS: u8 = 58;
+
+/// Pins of bank 0, whose events raise gpio_int[0]. That is the GPIO interrupt the BSPs register,
+/// the interrupts of the other banks stay disabled.
+const EVENT_PINS: u8 = 28;
+
+/// Pin functions, encoded as in `GPFSELn`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Input = 0b000,
+    Output = 0b001,
+    Alt0 = 0b100,
+    Alt1 = 0b101,
+    Alt2 = 0b110,
+    Alt3 = 0b111,
+    Alt4 = 0b011,
+    Alt5 = 0b010,
+}
+
+/// Internal pull resistor of a pin.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+/// Conditions that set a pin's bit in `GPEDS`. Level events keep firing while the level holds, so
+/// their handler has to make the source let go of the line.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    RisingEdge,
+    FallingEdge,
+    High,
+    Low,
+    AsyncRisingEdge,
+    AsyncFallingEdge,
+}
+
+/// Called from the interrupt handler with the number of the pin that saw an event.
+pub type Handler = fn(u8);
+
+/// Type states of a `Pin`.
+pub mod mode {
+    /// Just claimed, still in whatever function it had.
+    pub struct Unconfigured;
+    pub struct Input;
+    pub struct Output;
+    /// One of the alternate functions, driven by another peripheral.
+    pub struct Alt;
+}
+
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
 struct GPIOInner {
     registers: Registers,
+    /// One bit per pin that has an owner.
+    claimed: u64,
+    handlers: [Option<Handler>; NUM_PINS as usize],
 }
 
+/// The GPIO block. All cores share it, so its state is behind a `SpinLock`.
 pub struct GPIO {
-    inner: NullLock<GPIOInner>,
+    inner: SpinLock<GPIOInner>,
+}
+
+/// A pin with a single owner, obtained from `GPIO::claim` and released when dropped. The type
+/// parameter is its function, see `mode`.
+pub struct Pin<M> {
+    number: u8,
+    gpio: &'static GPIO,
+    mode: PhantomData<M>,
+}
+
+/// Register index and bit of `pin` in the registers with one bit per pin.
+fn bank(pin: u8) -> (usize, u32) {
+    (usize::from(pin / 32), 1 << (pin % 32))
 }
 
 impl GPIOInner {
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
-            registers: Registers::new(mmio_start_addr)
+            registers: Registers::new(mmio_start_addr),
+            claimed: 0,
+            handlers: [None; NUM_PINS as usize],
         }
     }
+
+    fn claim(&mut self, pin: u8) -> Result<(), &'static str> {
+        if pin >= NUM_PINS {
+            return Err("no such GPIO pin");
+        }
+        if self.claimed & (1 << pin) != 0 {
+            return Err("GPIO pin already claimed");
+        }
+
+        self.claimed |= 1 << pin;
+        Ok(())
+    }
+
+    fn release(&mut self, pin: u8) {
+        self.unlisten(pin);
+        self.claimed &= !(1 << pin);
+    }
+
+    fn event_enable(&self, event: Event) -> &[ReadWrite<u32>; 2] {
+        match event {
+            Event::RisingEdge => &self.registers.GPREN,
+            Event::FallingEdge => &self.registers.GPFEN,
+            Event::High => &self.registers.GPHEN,
+            Event::Low => &self.registers.GPLEN,
+            Event::AsyncRisingEdge => &self.registers.GPAREN,
+            Event::AsyncFallingEdge => &self.registers.GPAFEN,
+        }
+    }
+
+    fn listen(&mut self, pin: u8, event: Event, handler: Handler) -> Result<(), &'static str> {
+        if pin >= EVENT_PINS {
+            return Err("only events of GPIO0..=27 raise an interrupt");
+        }
+
+        let (index, bit) = bank(pin);
+        self.handlers[usize::from(pin)] = Some(handler);
+
+        // Drop anything latched before the handler was in place.
+        self.registers.GPEDS[index].set(bit);
+        let register = &self.event_enable(event)[index];
+        register.set(register.get() | bit);
+        Ok(())
+    }
+
+    fn unlisten(&mut self, pin: u8) {
+        use Event::*;
+
+        let (index, bit) = bank(pin);
+        for event in [RisingEdge, FallingEdge, High, Low, AsyncRisingEdge, AsyncFallingEdge] {
+            let register = &self.event_enable(event)[index];
+            register.set(register.get() & !bit);
+        }
+
+        self.registers.GPEDS[index].set(bit);
+        self.handlers[usize::from(pin)] = None;
+    }
+
+    /// Clear the pending events and return them, one bit per pin.
+    fn take_events(&mut self) -> u64 {
+        let low = self.registers.GPEDS[0].get();
+        let high = self.registers.GPEDS[1].get();
+        self.registers.GPEDS[0].set(low);
+        self.registers.GPEDS[1].set(high);
+
+        u64::from(high) << 32 | u64::from(low)
+    }
+
+    fn set_function(&mut self, pin: u8, function: Function) {
+        let register = &self.registers.GPFSEL[usize::from(pin / 10)];
+        let shift = u32::from(pin % 10) * 3;
+
+        let value = register.get() & !(0b111 << shift);
+        register.set(value | (function as u32) << shift);
+    }
+
+    fn set_level(&mut self, pin: u8, high: bool) {
+        let (index, bit) = bank(pin);
+        if high {
+            self.registers.GPSET[index].set(bit);
+        } else {
+            self.registers.GPCLR[index].set(bit);
+        }
+    }
+
+    fn is_high(&self, pin: u8) -> bool {
+        let (index, bit) = bank(pin);
+        self.registers.GPLEV[index].get() & bit != 0
+    }
+
+    /// The control signal has to be set up and held around the clock for 150 cycles each.
     #[cfg(feature = "bsp-rpi-3")]
-    fn disable_pud_14_15_bcm2837(&mut self) {
-        const DELAY: usize = 2000;
+    fn set_pull(&mut self, pin: u8, pull: Pull) {
+        // 150 cycles of the 250 MHz core clock are 0.6 µs.
+        const DELAY: Duration = Duration::from_micros(2);
 
-        self.registers.GPPUD.write(GPPUD::PUB::DISABLE);
+        let (index, bit) = bank(pin);
+        self.registers.GPPUD.write(match pull {
+            Pull::None => GPPUD::PUB::DISABLE,
+            Pull::Up => GPPUD::PUB::PULLUP,
+            Pull::Down => GPPUD::PUB::PULLDOWN,
+        });
 
-        cpu::spin_for_cycles(DELAY);
+        time::time_manager().spin_for(DELAY);
 
-        self.registers.GPPUDCLK0.write(
-            GPPUDCLK0::PUDCLK14::AssertClock + GPPUDCLK0::PUDCLK15::AssertClock
-        );
+        self.registers.GPPUDCLK[index].set(bit);
 
-        cpu::spin_for_cycles(DELAY);
+        time::time_manager().spin_for(DELAY);
 
         self.registers.GPPUD.write(GPPUD::PUB::DISABLE);
-        self.registers.GPPUDCLK0.set(0);
+        self.registers.GPPUDCLK[index].set(0);
     }
 
     #[cfg(feature = "bsp-rpi-4")]
-    fn disable_pub_14_15_bcm2711(&mut self) {
-        self.registers.GPIO_PUP_PDN_CNTRL_REG0.write(
-            GPIO_PUP_PDN_CNTRL_REG0::GPIO_PUP_PDN_CNTRL14::PullUp +
-                GPIO_PUP_PDN_CNTRL_REG0::GPIO_PUP_PDN_CNTRL15::PullUp
-        )
+    fn set_pull(&mut self, pin: u8, pull: Pull) {
+        let register = &self.registers.GPIO_PUP_PDN_CNTRL[usize::from(pin / 16)];
+        let shift = u32::from(pin % 16) * 2;
+        let pull: u32 = match pull {
+            Pull::None => 0b00,
+            Pull::Up => 0b01,
+            Pull::Down => 0b10,
+        };
+
+        let value = register.get() & !(0b11 << shift);
+        register.set(value | pull << shift);
     }
 
-    pub fn map_p1011_uart(&mut self) {
-        self.registers.GPFSEL1.write(
-            GPFSEL1::FSEL14::ALTFUN + GPFSEL1::FSEL15::ALTFUN
-        );
+    /// Route GPIO16 (CTS) and GPIO17 (RTS) to the PL011, or give them back.
+    #[cfg(not(feature = "mini-uart"))]
+    fn map_p1011_flow_control(&mut self, enable: bool) -> Result<(), &'static str> {
+        const PINS: [u8; 2] = [16, 17];
+
+        if !enable {
+            for pin in PINS {
+                self.set_function(pin, Function::Input);
+                self.release(pin);
+            }
+            return Ok(());
+        }
+
+        if PINS.iter().any(|&pin| self.claimed & (1 << pin) != 0) {
+            return Err("GPIO pin already claimed");
+        }
+        for pin in PINS {
+            self.claim(pin)?;
+            self.set_function(pin, Function::Alt3);
+        }
+        Ok(())
+    }
+
+    /// Route GPIO14 (TXD) and GPIO15 (RXD) to the UART selected by `function`.
+    fn map_uart(&mut self, function: Function) {
+        // Set up at init, before anybody else could claim the pins.
+        for pin in [14, 15] {
+            let _ = self.claim(pin);
+            self.set_function(pin, function);
+        }
 
         #[cfg(feature = "bsp-rpi-4")]
-        self.disable_pub_14_15_bcm2711();
+        for pin in [14, 15] {
+            self.set_pull(pin, Pull::Up);
+        }
         #[cfg(feature = "bsp-rpi-3")]
-        self.disable_pud_14_15_bcm2837();
+        for pin in [14, 15] {
+            self.set_pull(pin, Pull::None);
+        }
     }
 }
 
@@ -136,18 +351,151 @@ impl GPIO {
 
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
-            inner: NullLock::new(GPIOInner::new(mmio_start_addr))
+            inner: SpinLock::new(GPIOInner::new(mmio_start_addr))
         }
     }
 
+    #[cfg(not(feature = "mini-uart"))]
     pub fn map_p1011_uart(&self) {
-        self.inner.lock(|inner| inner.map_p1011_uart())
+        self.inner.lock(|inner| inner.map_uart(Function::Alt0))
+    }
+
+    #[cfg(feature = "mini-uart")]
+    pub fn map_mini_uart(&self) {
+        self.inner.lock(|inner| inner.map_uart(Function::Alt5))
+    }
+
+    /// Hand GPIO16/17 to the PL011 as CTS/RTS when `enable`, release them otherwise.
+    #[cfg(not(feature = "mini-uart"))]
+    pub fn map_p1011_flow_control(&self, enable: bool) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.map_p1011_flow_control(enable))
+    }
+
+    /// Take ownership of pin `number`. Fails if it does not exist or has an owner already.
+    pub fn claim(&'static self, number: u8) -> Result<Pin<mode::Unconfigured>, &'static str> {
+        self.inner.lock(|inner| inner.claim(number))?;
+
+        Ok(Pin { number, gpio: self, mode: PhantomData })
+    }
+
+    /// Run the handlers of the pins with pending events. Called by the handler of the GPIO
+    /// interrupt.
+    pub fn handle_events(&self) {
+        let mut pending = self.inner.lock(|inner| inner.take_events());
+
+        while pending != 0 {
+            let pin = pending.trailing_zeros() as u8;
+            pending &= pending - 1;
+
+            // Handlers may claim or reconfigure pins, so the lock is not held while they run.
+            let handler =
+                self.inner.lock(|inner| inner.handlers.get(usize::from(pin)).copied().flatten());
+            if let Some(handler) = handler {
+                handler(pin);
+            }
+        }
+    }
+}
+
+impl<M> Pin<M> {
+    /// Switch to `function` and change the type state to `N`, keeping the claim.
+    fn into_mode<N>(self, function: Function) -> Pin<N> {
+        let pin = Pin { number: self.number, gpio: self.gpio, mode: PhantomData };
+        self.gpio.inner.lock(|inner| inner.set_function(pin.number, function));
+
+        // The new handle owns the pin now.
+        core::mem::forget(self);
+        pin
+    }
+
+    pub fn into_input(self) -> Pin<mode::Input> {
+        self.into_mode(Function::Input)
+    }
+
+    pub fn into_output(self) -> Pin<mode::Output> {
+        self.into_mode(Function::Output)
+    }
+
+    /// Hand the pin to a peripheral. `function` should be one of the `AltN` functions.
+    pub fn into_alt(self, function: Function) -> Pin<mode::Alt> {
+        self.into_mode(function)
+    }
+
+    pub fn set_pull(&self, pull: Pull) {
+        self.gpio.inner.lock(|inner| inner.set_pull(self.number, pull))
+    }
+
+    /// The level on the pin, whatever drives it.
+    pub fn is_high(&self) -> bool {
+        self.gpio.inner.lock(|inner| inner.is_high(self.number))
+    }
+
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
     }
 }
 
+impl Pin<mode::Input> {
+    /// Call `handler` from the GPIO interrupt whenever `event` happens on this pin. Several events
+    /// can be enabled this way. They share the handler set last.
+    ///
+    /// Fails for pins above GPIO27, whose events do not raise the interrupt.
+    pub fn listen(&self, event: Event, handler: Handler) -> Result<(), &'static str> {
+        self.gpio.inner.lock(|inner| inner.listen(self.number, event, handler))
+    }
+
+    /// Disable all events of this pin. Done on drop too.
+    pub fn unlisten(&self) {
+        self.gpio.inner.lock(|inner| inner.unlisten(self.number))
+    }
+}
+
+impl Pin<mode::Output> {
+    pub fn set_high(&self) {
+        self.set(true)
+    }
+
+    pub fn set_low(&self) {
+        self.set(false)
+    }
+
+    pub fn set(&self, high: bool) {
+        self.gpio.inner.lock(|inner| inner.set_level(self.number, high))
+    }
+
+    pub fn toggle(&self) {
+        self.set(self.is_low())
+    }
+}
+
+impl<M> Drop for Pin<M> {
+    fn drop(&mut self) {
+        self.gpio.inner.lock(|inner| inner.release(self.number))
+    }
+}
 
 impl DeviceDriver for GPIO {
     fn compatible(&self) -> &'static str {
         Self::COMPATIBLE
     }
-}
\ No newline at end of file
+
+    fn register_and_enable_irq_handler(
+        &'static self,
+        irq_number: &IRQNumber,
+    ) -> Result<(), &'static str> {
+        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self);
+
+        irq_manager().register_handler(descriptor)?;
+        irq_manager().enable(irq_number);
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for GPIO {
+    fn handle(&self) -> Result<(), &'static str> {
+        self.handle_events();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+//! The BCM2837 interrupt controllers: the ARM control block's peripheral interrupt controller,
+//! which collects the interrupts of the peripherals, and the per-core local interrupt controller
+//! in front of it.
+
+mod local_ic;
+mod peripheral_ic;
+
+use core::fmt;
+use crate::driver::interface::DeviceDriver;
+use crate::exception::asynchronous::{interface, BoundedUsize, IRQContext, IRQHandlerDescriptor};
+
+/// Interrupts of the local controller, numbered by their bit in the core's IRQ source register:
+/// the core timers (0..=3) and the mailboxes (4..=7).
+pub type LocalIRQ = BoundedUsize<{ InterruptController::MAX_LOCAL_IRQ_NUMBER }>;
+
+/// Interrupts of the peripheral controller: bank 1 (0..=31) and bank 2 (32..=63).
+pub type PeripheralIRQ = BoundedUsize<{ InterruptController::MAX_PERIPHERAL_IRQ_NUMBER }>;
+
+/// Used for the associated type of trait [`interface::IRQManager`].
+#[derive(Copy, Clone)]
+pub enum IRQNumber {
+    /// No driver uses one yet: the core timers and mailboxes are left to the payload.
+    #[allow(dead_code)]
+    Local(LocalIRQ),
+    Peripheral(PeripheralIRQ),
+}
+
+/// Representation of the Interrupt Controller.
+pub struct InterruptController {
+    local: local_ic::LocalIC,
+    periph: peripheral_ic::PeripheralIC,
+}
+
+impl fmt::Display for IRQNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Local(number) => write!(f, "Local({})", number),
+            Self::Peripheral(number) => write!(f, "Peripheral({})", number),
+        }
+    }
+}
+
+impl InterruptController {
+    const MAX_LOCAL_IRQ_NUMBER: usize = 7;
+    const MAX_PERIPHERAL_IRQ_NUMBER: usize = 63;
+
+    pub const COMPATIBLE: &'static str = "BCM Interrupt Controller";
+
+    /// `local_mmio_start_addr` is the start of the local peripherals (core timers, mailboxes),
+    /// `periph_mmio_start_addr` the start of the interrupt registers in the ARM control block.
+    pub const unsafe fn new(local_mmio_start_addr: usize, periph_mmio_start_addr: usize) -> Self {
+        Self {
+            local: local_ic::LocalIC::new(local_mmio_start_addr),
+            periph: peripheral_ic::PeripheralIC::new(periph_mmio_start_addr),
+        }
+    }
+}
+
+impl DeviceDriver for InterruptController {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        // Start from nothing enabled, whatever a payload that came back left behind.
+        self.local.disable_all();
+        self.periph.disable_all();
+        self.local.init();
+        self.periph.init();
+
+        Ok(())
+    }
+}
+
+impl interface::IRQManager for InterruptController {
+    type IRQNumberType = IRQNumber;
+
+    fn register_handler(
+        &self,
+        irq_handler_descriptor: IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        match irq_handler_descriptor.number() {
+            IRQNumber::Local(lirq) => {
+                let local_descriptor = IRQHandlerDescriptor::new(
+                    lirq,
+                    irq_handler_descriptor.name(),
+                    irq_handler_descriptor.handler(),
+                );
+
+                self.local.register_handler(local_descriptor)
+            }
+            IRQNumber::Peripheral(pirq) => {
+                let periph_descriptor = IRQHandlerDescriptor::new(
+                    pirq,
+                    irq_handler_descriptor.name(),
+                    irq_handler_descriptor.handler(),
+                );
+
+                self.periph.register_handler(periph_descriptor)
+            }
+        }
+    }
+
+    fn enable(&self, irq: &Self::IRQNumberType) {
+        match irq {
+            IRQNumber::Local(lirq) => self.local.enable(lirq),
+            IRQNumber::Peripheral(pirq) => self.periph.enable(pirq),
+        }
+    }
+
+    fn disable_all(&self) {
+        self.local.disable_all();
+        self.periph.disable_all();
+    }
+
+    fn handle_pending_irqs<'irq_context>(&'irq_context self, ic: &IRQContext<'irq_context>) {
+        // The peripheral controller reaches the core as the local controller's GPU interrupt.
+        if self.local.handle_pending_irqs(ic) {
+            self.periph.handle_pending_irqs(ic)
+        }
+    }
+
+    fn print_handler(&self) {
+        self.local.print_handler();
+        self.periph.print_handler();
+    }
+}
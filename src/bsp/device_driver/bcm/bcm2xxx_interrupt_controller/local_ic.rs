@@ -0,0 +1,154 @@
+//! Local Interrupt Controller Driver, the per-core interrupt routing of the BCM2836 and later.
+//!
+//! Only core 0, the boot core, is served.
+
+use tock_registers::{register_structs, registers::ReadOnly, registers::ReadWrite};
+use tock_registers::interfaces::{Readable, Writeable};
+use super::{InterruptController, LocalIRQ};
+use crate::bsp::device_driver::common::MMIODerefWrapper;
+use crate::exception::asynchronous::{IRQContext, IRQHandlerDescriptor};
+use crate::synchronization::interface::Mutex;
+use crate::synchronization::IRQSafeNullLock;
+use crate::println;
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        /// Core that gets the peripheral interrupts, in bits 0..=1.
+        (0x0c => GPU_INT_ROUTING: ReadWrite<u32>),
+        (0x10 => _reserved2),
+        /// Per core: bits 0..=3 route the core timer interrupts to IRQ.
+        (0x40 => TIMER_INT_CONTROL: [ReadWrite<u32>; 4]),
+        /// Per core: bits 0..=3 route the mailbox interrupts to IRQ.
+        (0x50 => MAILBOX_INT_CONTROL: [ReadWrite<u32>; 4]),
+        /// Per core: pending interrupts, one bit per `LocalIRQ`, the peripheral controller at
+        /// `GPU_PENDING`.
+        (0x60 => IRQ_SOURCE: [ReadOnly<u32>; 4]),
+        (0x70 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+type HandlerTable = [Option<IRQHandlerDescriptor<LocalIRQ>>;
+    InterruptController::MAX_LOCAL_IRQ_NUMBER + 1];
+
+/// Bit of `IRQ_SOURCE` that stands for the peripheral controller.
+const GPU_PENDING: u32 = 1 << 8;
+
+/// The first `LocalIRQ` that is a mailbox.
+const FIRST_MAILBOX: usize = 4;
+
+const CORE: usize = 0;
+
+struct LocalICInner {
+    registers: Registers,
+    handler_table: HandlerTable,
+}
+
+/// Representation of the local interrupt controller.
+pub struct LocalIC {
+    inner: IRQSafeNullLock<LocalICInner>,
+}
+
+impl LocalICInner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            handler_table: [None; InterruptController::MAX_LOCAL_IRQ_NUMBER + 1],
+        }
+    }
+
+    /// The control register of `irq` and its bit there.
+    fn control(&self, irq: usize) -> (&ReadWrite<u32>, u32) {
+        if irq < FIRST_MAILBOX {
+            (&self.registers.TIMER_INT_CONTROL[CORE], 1 << irq)
+        } else {
+            (&self.registers.MAILBOX_INT_CONTROL[CORE], 1 << (irq - FIRST_MAILBOX))
+        }
+    }
+}
+
+impl LocalIC {
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(LocalICInner::new(mmio_start_addr)),
+        }
+    }
+
+    pub fn init(&self) {
+        self.inner.lock(|inner| {
+            // The table is in `.data` and survives a payload coming back to the loader.
+            inner.handler_table = [None; InterruptController::MAX_LOCAL_IRQ_NUMBER + 1];
+
+            // Peripheral interrupts go to the boot core.
+            inner.registers.GPU_INT_ROUTING.set(CORE as u32)
+        })
+    }
+
+    pub fn register_handler(
+        &self,
+        descriptor: IRQHandlerDescriptor<LocalIRQ>,
+    ) -> Result<(), &'static str> {
+        self.inner.lock(|inner| {
+            let slot = &mut inner.handler_table[descriptor.number().get()];
+            if slot.is_some() {
+                return Err("IRQ handler already registered");
+            }
+
+            *slot = Some(descriptor);
+            Ok(())
+        })
+    }
+
+    pub fn enable(&self, irq: &LocalIRQ) {
+        self.inner.lock(|inner| {
+            let (register, bit) = inner.control(irq.get());
+            register.set(register.get() | bit);
+        })
+    }
+
+    pub fn disable_all(&self) {
+        self.inner.lock(|inner| {
+            inner.registers.TIMER_INT_CONTROL[CORE].set(0);
+            inner.registers.MAILBOX_INT_CONTROL[CORE].set(0);
+        })
+    }
+
+    /// Run the handlers of the pending local interrupts. Returns whether the peripheral
+    /// controller has interrupts pending as well.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        _ic: &IRQContext<'irq_context>,
+    ) -> bool {
+        let source = self.inner.lock(|inner| inner.registers.IRQ_SOURCE[CORE].get());
+
+        let mut pending = source & ((1 << (InterruptController::MAX_LOCAL_IRQ_NUMBER + 1)) - 1);
+        while pending != 0 {
+            let irq = pending.trailing_zeros() as usize;
+            pending &= pending - 1;
+
+            match self.inner.lock(|inner| inner.handler_table[irq]) {
+                None => panic!("No handler registered for IRQ Local({})", irq),
+                Some(descriptor) => {
+                    // Call the IRQ handler. Panics on failure.
+                    descriptor.handler().handle().expect("Error handling IRQ");
+                }
+            }
+        }
+
+        source & GPU_PENDING != 0
+    }
+
+    pub fn print_handler(&self) {
+        self.inner.lock(|inner| {
+            for (i, descriptor) in inner.handler_table.iter().enumerate() {
+                if let Some(descriptor) = descriptor {
+                    println!("      Local({}). {}", i, descriptor.name());
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,131 @@
+//! Peripheral Interrupt Controller Driver.
+
+use tock_registers::{register_structs, registers::ReadOnly, registers::WriteOnly};
+use tock_registers::interfaces::{Readable, Writeable};
+use super::{InterruptController, PeripheralIRQ};
+use crate::bsp::device_driver::common::MMIODerefWrapper;
+use crate::exception::asynchronous::{IRQContext, IRQHandlerDescriptor};
+use crate::synchronization::interface::Mutex;
+use crate::synchronization::IRQSafeNullLock;
+use crate::println;
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        /// The basic ARM interrupts, plus summary bits for the banks and shortcuts for some
+        /// GPU interrupts. Interrupts with a shortcut do not show in the summary bits, so the
+        /// banks are always read in full.
+        (0x00 => IRQ_BASIC_PENDING: ReadOnly<u32>),
+        (0x04 => PENDING: [ReadOnly<u32>; 2]),
+        (0x0c => _reserved1),
+        (0x10 => ENABLE: [WriteOnly<u32>; 2]),
+        (0x18 => ENABLE_BASIC: WriteOnly<u32>),
+        (0x1c => DISABLE: [WriteOnly<u32>; 2]),
+        (0x24 => DISABLE_BASIC: WriteOnly<u32>),
+        (0x28 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+type HandlerTable = [Option<IRQHandlerDescriptor<PeripheralIRQ>>;
+    InterruptController::MAX_PERIPHERAL_IRQ_NUMBER + 1];
+
+struct PeripheralICInner {
+    registers: Registers,
+    handler_table: HandlerTable,
+}
+
+/// Representation of the peripheral interrupt controller.
+pub struct PeripheralIC {
+    inner: IRQSafeNullLock<PeripheralICInner>,
+}
+
+impl PeripheralICInner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            handler_table: [None; InterruptController::MAX_PERIPHERAL_IRQ_NUMBER + 1],
+        }
+    }
+
+    /// Query the list of pending IRQs.
+    fn pending_irqs(&self) -> u64 {
+        let low = self.registers.PENDING[0].get();
+        let high = self.registers.PENDING[1].get();
+
+        u64::from(high) << 32 | u64::from(low)
+    }
+}
+
+impl PeripheralIC {
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(PeripheralICInner::new(mmio_start_addr)),
+        }
+    }
+
+    pub fn init(&self) {
+        // The table is in `.data` and survives a payload coming back to the loader.
+        self.inner.lock(|inner| {
+            inner.handler_table = [None; InterruptController::MAX_PERIPHERAL_IRQ_NUMBER + 1]
+        })
+    }
+
+    pub fn register_handler(
+        &self,
+        descriptor: IRQHandlerDescriptor<PeripheralIRQ>,
+    ) -> Result<(), &'static str> {
+        self.inner.lock(|inner| {
+            let slot = &mut inner.handler_table[descriptor.number().get()];
+            if slot.is_some() {
+                return Err("IRQ handler already registered");
+            }
+
+            *slot = Some(descriptor);
+            Ok(())
+        })
+    }
+
+    pub fn enable(&self, irq: &PeripheralIRQ) {
+        let number = irq.get();
+        self.inner.lock(|inner| inner.registers.ENABLE[number / 32].set(1 << (number % 32)))
+    }
+
+    pub fn disable_all(&self) {
+        self.inner.lock(|inner| {
+            inner.registers.DISABLE[0].set(u32::MAX);
+            inner.registers.DISABLE[1].set(u32::MAX);
+            inner.registers.DISABLE_BASIC.set(u32::MAX);
+        })
+    }
+
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn handle_pending_irqs<'irq_context>(&'irq_context self, _ic: &IRQContext<'irq_context>) {
+        let mut pending = self.inner.lock(|inner| inner.pending_irqs());
+
+        while pending != 0 {
+            let irq = pending.trailing_zeros() as usize;
+            pending &= pending - 1;
+
+            // The handler runs without the lock held.
+            match self.inner.lock(|inner| inner.handler_table[irq]) {
+                None => panic!("No handler registered for IRQ {}", irq),
+                Some(descriptor) => {
+                    // Call the IRQ handler. Panics on failure.
+                    descriptor.handler().handle().expect("Error handling IRQ");
+                }
+            }
+        }
+    }
+
+    pub fn print_handler(&self) {
+        self.inner.lock(|inner| {
+            for (i, descriptor) in inner.handler_table.iter().enumerate() {
+                if let Some(descriptor) = descriptor {
+                    println!("      Peripheral({}). {}", i, descriptor.name());
+                }
+            }
+        })
+    }
+}
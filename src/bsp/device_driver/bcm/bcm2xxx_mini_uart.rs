@@ -0,0 +1,273 @@
+use core::fmt;
+use core::fmt::Arguments;
+use core::time::Duration;
+use tock_registers::{register_bitfields, register_structs, registers::ReadWrite, registers::ReadOnly, registers::WriteOnly};
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+use crate::{console, cpu, time};
+use crate::bsp::device_driver::common::MMIODerefWrapper;
+use crate::bsp::device_driver::DEFAULT_BAUD_RATE;
+use crate::driver::interface::DeviceDriver;
+use crate::synchronization::interface::Mutex;
+use crate::synchronization::IRQSafeNullLock;
+
+register_bitfields! {
+    u32,
+    /// Auxiliary enables. The mini UART's registers cannot be accessed while it is disabled.
+    AUX_ENABLES [
+        MINI_UART OFFSET(0) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+    /// Interrupt identify register, also used to clear the FIFOs.
+    AUX_MU_IIR [
+        /// Writing 1 clears the receive FIFO.
+        CLEAR_RX OFFSET(1) NUMBITS(1) [],
+        /// Writing 1 clears the transmit FIFO.
+        CLEAR_TX OFFSET(2) NUMBITS(1) []
+    ],
+    /// Line control register.
+    AUX_MU_LCR [
+        /// The datasheet documents bit 0 only, but 8-bit mode needs both bits set (BCM2835
+        /// errata).
+        DATA_SIZE OFFSET(0) NUMBITS(2) [
+            SevenBit = 0b00,
+            EightBit = 0b11
+        ]
+    ],
+    /// Line status register.
+    AUX_MU_LSR [
+        /// Set when the transmit FIFO is empty and the transmitter idle.
+        TX_IDLE OFFSET(6) NUMBITS(1) [],
+        /// Set when the transmit FIFO can accept at least one byte.
+        TX_EMPTY OFFSET(5) NUMBITS(1) [],
+        /// Set when the receive FIFO holds at least one byte.
+        DATA_READY OFFSET(0) NUMBITS(1) []
+    ],
+    /// Extra control register.
+    AUX_MU_CNTL [
+        TX_ENABLE OFFSET(1) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+        RX_ENABLE OFFSET(0) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+    /// Baud rate register. Baud rate = system clock / (8 * (BAUD + 1)).
+    AUX_MU_BAUD [
+        BAUD OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        (0x04 => AUX_ENABLES: ReadWrite<u32, AUX_ENABLES::Register>),
+        (0x08 => _reserved2),
+        (0x40 => AUX_MU_IO: ReadWrite<u32>),
+        (0x44 => AUX_MU_IER: WriteOnly<u32>),
+        (0x48 => AUX_MU_IIR: WriteOnly<u32, AUX_MU_IIR::Register>),
+        (0x4c => AUX_MU_LCR: WriteOnly<u32, AUX_MU_LCR::Register>),
+        (0x50 => AUX_MU_MCR: WriteOnly<u32>),
+        (0x54 => AUX_MU_LSR: ReadOnly<u32, AUX_MU_LSR::Register>),
+        (0x58 => _reserved3),
+        (0x60 => AUX_MU_CNTL: WriteOnly<u32, AUX_MU_CNTL::Register>),
+        (0x64 => _reserved4),
+        (0x68 => AUX_MU_BAUD: WriteOnly<u32, AUX_MU_BAUD::Register>),
+        (0x6c => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+struct MiniUartInner {
+    registers: Registers,
+    system_clk: u32,
+    chars_written: usize,
+    chars_read: usize,
+}
+
+/// The mini UART of the auxiliary peripherals. Its baud rate is derived from the VPU core clock,
+/// which the firmware only keeps fixed with `enable_uart=1` in config.txt.
+pub struct MiniUart {
+    inner: IRQSafeNullLock<MiniUartInner>,
+}
+
+/// The `AUX_MU_BAUD` value for `baud`, rounded to the nearest rate.
+fn baud_register(baud: u32, system_clk: u32) -> Result<u32, &'static str> {
+    if baud == 0 {
+        return Err("baud rate must not be zero");
+    }
+
+    let divisor = (u64::from(system_clk) + 4 * u64::from(baud)) / (8 * u64::from(baud));
+    if divisor == 0 || divisor > 0x1_0000 {
+        return Err("baud rate not reachable with this system clock");
+    }
+
+    Ok(divisor as u32 - 1)
+}
+
+impl MiniUartInner {
+    pub const unsafe fn new(mmio_start_addr: usize, system_clk: u32) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            system_clk,
+            chars_written: 0,
+            chars_read: 0,
+        }
+    }
+
+    pub fn init(&mut self) {
+        // 返回 loader 时 .data 不会重新初始化，计数要在这里清零
+        self.chars_written = 0;
+        self.chars_read = 0;
+
+        // SPI1 和 SPI2 的使能位也在这个寄存器里，不能直接覆盖
+        self.registers.AUX_ENABLES.modify(AUX_ENABLES::MINI_UART::Enabled);
+
+        // 不使用中断和流控
+        self.registers.AUX_MU_IER.set(0);
+        self.registers.AUX_MU_MCR.set(0);
+
+        self.set_baud_rate(DEFAULT_BAUD_RATE, self.system_clk).unwrap();
+    }
+
+    /// Reprogram the UART for `baud`, given the system clock `system_clk` in Hz.
+    ///
+    /// Waits for pending output to go out first. Characters in flight on the receiving side are
+    /// lost.
+    pub fn set_baud_rate(&self, baud: u32, system_clk: u32) -> Result<(), &'static str> {
+        let divisor = baud_register(baud, system_clk)?;
+
+        self.flush();
+        // 修改波特率前先关闭收发
+        self.registers.AUX_MU_CNTL.set(0);
+
+        self.registers.AUX_MU_LCR.write(AUX_MU_LCR::DATA_SIZE::EightBit);
+        self.registers.AUX_MU_IIR.write(AUX_MU_IIR::CLEAR_RX::SET + AUX_MU_IIR::CLEAR_TX::SET);
+        self.registers.AUX_MU_BAUD.write(AUX_MU_BAUD::BAUD.val(divisor));
+
+        self.registers
+            .AUX_MU_CNTL
+            .write(AUX_MU_CNTL::RX_ENABLE::Enabled + AUX_MU_CNTL::TX_ENABLE::Enabled);
+
+        Ok(())
+    }
+
+    pub fn flush(&self) {
+        // 等待发送 FIFO 清空且最后一个字符移出
+        while !self.registers.AUX_MU_LSR.matches_all(AUX_MU_LSR::TX_IDLE::SET) {
+            cpu::nop();
+        }
+    }
+
+    fn write_char(&mut self, c: char) {
+        while !self.registers.AUX_MU_LSR.matches_all(AUX_MU_LSR::TX_EMPTY::SET) {
+            cpu::nop();
+        }
+        self.registers.AUX_MU_IO.set(c as u32);
+        self.chars_written += 1;
+    }
+
+    /// The next received character, if there is one.
+    fn try_read_char(&mut self) -> Option<char> {
+        if !self.registers.AUX_MU_LSR.matches_all(AUX_MU_LSR::DATA_READY::SET) {
+            return None;
+        }
+
+        let ret = self.registers.AUX_MU_IO.get() as u8 as char;
+        self.chars_read += 1;
+
+        Some(ret)
+    }
+}
+
+impl fmt::Write for MiniUartInner {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+
+        Ok(())
+    }
+}
+
+impl MiniUart {
+    pub const COMPATIBLE: &'static str = "BCM AUX mini UART";
+
+    /// `mmio_start_addr` is the start of the auxiliary peripherals, `system_clk` the VPU core clock
+    /// in Hz.
+    pub const unsafe fn new(mmio_start_addr: usize, system_clk: u32) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(MiniUartInner::new(mmio_start_addr, system_clk))
+        }
+    }
+
+    /// Switch to `baud`, see `MiniUartInner::set_baud_rate`.
+    pub fn set_baud_rate(&self, baud: u32, system_clk: u32) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.set_baud_rate(baud, system_clk))
+    }
+}
+
+impl DeviceDriver for MiniUart {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.init());
+
+        Ok(())
+    }
+}
+
+impl console::interface::Read for MiniUart {
+    fn read_char_timeout(&self, timeout: Duration) -> Option<char> {
+        let deadline = time::time_manager().uptime() + timeout;
+
+        loop {
+            let c = self.inner.lock(|inner| inner.try_read_char());
+            if c.is_some() || time::time_manager().uptime() >= deadline {
+                return c;
+            }
+        }
+    }
+
+    fn clear_rx(&self) {
+        while self.inner
+            .lock(|inner| inner.try_read_char())
+            .is_some()
+        {}
+    }
+}
+
+impl console::interface::Write for MiniUart {
+    fn write_char(&self, c: char) {
+        self.inner.lock(|inner| inner.write_char(c));
+    }
+
+    fn write_fmt(&self, arg: Arguments) -> fmt::Result {
+        self.inner.lock(|inner| fmt::Write::write_fmt(inner, arg))
+    }
+
+    fn flush(&self) {
+        self.inner.lock(|inner| inner.flush());
+    }
+}
+
+impl console::interface::Statistics for MiniUart {
+    fn chars_written(&self) -> usize {
+        self.inner.lock(|inner| inner.chars_written)
+    }
+
+    fn chars_read(&self) -> usize {
+        self.inner.lock(|inner| inner.chars_read)
+    }
+}
+
+impl console::interface::All for MiniUart {
+
+}
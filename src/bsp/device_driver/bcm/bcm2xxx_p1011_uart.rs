@@ -1,15 +1,49 @@
+mod divisor;
+
 use core::fmt;
 use core::fmt::Arguments;
+use core::time::Duration;
 use tock_registers::{register_bitfields, register_structs, registers::ReadWrite, registers::ReadOnly, registers::WriteOnly};
-use tock_registers::interfaces::{Readable, Writeable};
-use crate::{console, cpu};
-use crate::bsp::device_driver::common::MMIODerefWrapper;
+use tock_registers::fields::FieldValue;
+use tock_registers::LocalRegisterCopy;
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+use crate::{console, cpu, time};
+use crate::bsp::device_driver::common::{
+    DataBits, LineConfig, MMIODerefWrapper, Parity, RingBuffer, StopBits,
+};
+use crate::bsp::device_driver::DEFAULT_BAUD_RATE;
 use crate::driver::interface::DeviceDriver;
+use crate::exception::asynchronous::{irq_manager, IRQHandlerDescriptor, IRQNumber};
+use crate::exception;
 use crate::synchronization::interface::Mutex;
-use crate::synchronization::NullLock;
+use crate::synchronization::IRQSafeNullLock;
+use divisor::baud_divisor;
 
 register_bitfields! {
     u32,
+    /// DR Register
+    /// Received characters come with the errors detected while receiving them.
+    DR [
+        /// Overrun error. The receive FIFO was full when this character arrived, characters
+        /// were lost before it.
+        OE OFFSET(11) NUMBITS(1) [],
+        /// Break error. The line was held low for longer than a full frame.
+        BE OFFSET(10) NUMBITS(1) [],
+        /// Parity error.
+        PE OFFSET(9) NUMBITS(1) [],
+        /// Framing error. The character had no valid stop bit.
+        FE OFFSET(8) NUMBITS(1) [],
+        DATA OFFSET(0) NUMBITS(8) []
+    ],
+    /// RSR Register
+    /// Receive status. Overruns show up here as soon as a character is lost, DR only flags them on
+    /// the next character that makes it into the FIFO.
+    RSR [
+        OE OFFSET(3) NUMBITS(1) [],
+        BE OFFSET(2) NUMBITS(1) [],
+        PE OFFSET(1) NUMBITS(1) [],
+        FE OFFSET(0) NUMBITS(1) []
+    ],
     /// FR Register
     /// The UART_FR Register is the flag register
     FR [
@@ -71,9 +105,34 @@ register_bitfields! {
         FEN  OFFSET(4) NUMBITS(1) [
             FifosDisabled = 0,
             FifosEnabled = 1
+        ],
+        /// Two stop bits select.
+        STP2 OFFSET(3) NUMBITS(1) [
+            One = 0,
+            Two = 1
+        ],
+        /// Even parity select, when PEN is set.
+        EPS OFFSET(2) NUMBITS(1) [
+            Odd = 0,
+            Even = 1
+        ],
+        /// Parity enable.
+        PEN OFFSET(1) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
         ]
     ],
     CR [
+        /// CTS hardware flow control: only transmit while nCTS is asserted.
+        CTSEN OFFSET(15) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+        /// RTS hardware flow control: assert nRTS only while the receive FIFO has room.
+        RTSEN OFFSET(14) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
         RXE OFFSET(9) NUMBITS(1) [
             Disabled = 0,
             Enabled = 1
@@ -89,6 +148,38 @@ register_bitfields! {
             Enabled = 1
         ]
     ],
+    /// Interrupt FIFO level select. The receive interrupt fires when the RX FIFO fills up to its
+    /// level, the transmit interrupt when the TX FIFO drains down to its level.
+    IFLS [
+        RXIFLSEL OFFSET(3) NUMBITS(3) [
+            OneEighth = 0b000,
+            OneQuarter = 0b001,
+            OneHalf = 0b010,
+            ThreeQuarters = 0b011,
+            SevenEighths = 0b100
+        ],
+        TXIFLSEL OFFSET(0) NUMBITS(3) [
+            OneEighth = 0b000,
+            OneQuarter = 0b001,
+            OneHalf = 0b010,
+            ThreeQuarters = 0b011,
+            SevenEighths = 0b100
+        ]
+    ],
+    /// Interrupt mask set/clear. 1 enables the interrupt.
+    IMSC [
+        /// Receive timeout: the RX FIFO holds data below its level and nothing arrived for 32 bit
+        /// periods.
+        RTIM OFFSET(6) NUMBITS(1) [],
+        TXIM OFFSET(5) NUMBITS(1) [],
+        RXIM OFFSET(4) NUMBITS(1) []
+    ],
+    /// Masked interrupt status.
+    MIS [
+        RTMIS OFFSET(6) NUMBITS(1) [],
+        TXMIS OFFSET(5) NUMBITS(1) [],
+        RXMIS OFFSET(4) NUMBITS(1) []
+    ],
     ICR [
         /// Meta field for all pending interrupts.
         ALL OFFSET(0) NUMBITS(11) []
@@ -98,15 +189,20 @@ register_bitfields! {
 register_structs! {
     #[allow(non_snake_case)]
     RegisterBlock {
-        (0x00 => DR: ReadWrite<u32>),
-        (0x04 => _reserved1),
+        (0x00 => DR: ReadWrite<u32,DR::Register>),
+        /// Receive status (read) / error clear (write). Any write clears all bits.
+        (0x04 => RSRECR: ReadWrite<u32,RSR::Register>),
+        (0x08 => _reserved1),
         (0x18 => FR: ReadOnly<u32,FR::Register>),
         (0x1c => _reserved2),
         (0x24 => IBRD: WriteOnly<u32,IBRD::Register>),
         (0x28 => FBRD: WriteOnly<u32,FBRD::Register>),
         (0x2c => LCR_H: WriteOnly<u32,LCR_H::Register>),
         (0x30 => CR: WriteOnly<u32,CR::Register>),
-        (0x34 => _reserved3),
+        (0x34 => IFLS: ReadWrite<u32,IFLS::Register>),
+        (0x38 => IMSC: ReadWrite<u32,IMSC::Register>),
+        (0x3c => _reserved3),
+        (0x40 => MIS: ReadOnly<u32,MIS::Register>),
         (0x44 => ICR: WriteOnly<u32,ICR::Register>),
         (0x48 => @END),
     }
@@ -114,86 +210,250 @@ register_structs! {
 
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
-#[derive(PartialEq)]
-enum BlockingMode {
-    Blocking,
-    NonBlocking,
+/// LCR_H for the frame format in `line`, with the FIFOs on.
+fn lcr_h(line: &LineConfig) -> FieldValue<u32, LCR_H::Register> {
+    let parity = match line.parity {
+        Parity::None => LCR_H::PEN::Disabled,
+        Parity::Even => LCR_H::PEN::Enabled + LCR_H::EPS::Even,
+        Parity::Odd => LCR_H::PEN::Enabled + LCR_H::EPS::Odd,
+    };
+    let stop_bits = match line.stop_bits {
+        StopBits::One => LCR_H::STP2::One,
+        StopBits::Two => LCR_H::STP2::Two,
+    };
+
+    let data_bits = match line.data_bits {
+        DataBits::Five => LCR_H::WLEN::FiveBit,
+        DataBits::Six => LCR_H::WLEN::SixBit,
+        DataBits::Seven => LCR_H::WLEN::SevenBit,
+        DataBits::Eight => LCR_H::WLEN::EightBit,
+    };
+
+    data_bits + parity + stop_bits + LCR_H::FEN::FifosEnabled
+}
+
+/// CR for the flow control in `line`, with the UART on.
+fn cr(line: &LineConfig) -> FieldValue<u32, CR::Register> {
+    let flow_control = if line.flow_control {
+        CR::CTSEN::Enabled + CR::RTSEN::Enabled
+    } else {
+        CR::CTSEN::Disabled + CR::RTSEN::Disabled
+    };
+
+    flow_control + CR::RXE::Enabled + CR::TXE::Enabled + CR::UARTEN::Enabled
+}
+
+/// Receive errors since `init`. Overruns as flagged in RSR, the others as flagged in DR.
+struct ErrorCounts {
+    framing: usize,
+    parity: usize,
+    breaks: usize,
+    overruns: usize,
 }
 
+/// Bytes received but not read yet. Big enough to ride out a while of work without polling.
+const RX_BUFFER_SIZE: usize = 2048;
+
+/// Bytes written but not handed to the TX FIFO yet.
+const TX_BUFFER_SIZE: usize = 512;
+
 struct PL1011UartInner {
     registers: Registers,
+    uart_clk: u32,
+    baud: u32,
+    line: LineConfig,
+    rx: RingBuffer<RX_BUFFER_SIZE>,
+    tx: RingBuffer<TX_BUFFER_SIZE>,
     chars_written: usize,
     chars_read: usize,
+    errors: ErrorCounts,
 }
 
 pub struct PL1011Uart {
-    inner: NullLock<PL1011UartInner>,
+    inner: IRQSafeNullLock<PL1011UartInner>,
 }
 
 impl PL1011UartInner {
-    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+    pub const unsafe fn new(mmio_start_addr: usize, uart_clk: u32) -> Self {
         Self {
             registers: Registers::new(mmio_start_addr),
+            uart_clk,
+            baud: DEFAULT_BAUD_RATE,
+            line: LineConfig::DEFAULT,
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
             chars_written: 0,
             chars_read: 0,
+            errors: ErrorCounts { framing: 0, parity: 0, breaks: 0, overruns: 0 },
         }
     }
-    pub fn init(&self) {
-        self.flush();
-        // 把CR寄存器置0
-        self.registers.CR.set(0);
+    pub fn init(&mut self) {
+        // 返回 loader 时 .data 不会重新初始化，计数要在这里清零
+        self.chars_written = 0;
+        self.chars_read = 0;
+        self.errors = ErrorCounts { framing: 0, parity: 0, breaks: 0, overruns: 0 };
+
         // 清空ICR寄存器
         self.registers.ICR.write(ICR::ALL::CLEAR);
 
-        // 设置波特率，值为 921_600
+        // 设置波特率，48MHz时钟下 921_600 对应 IBRD=3, FBRD=16
+        self.set_baud_rate(DEFAULT_BAUD_RATE, self.uart_clk).unwrap();
+
+        // 接收 FIFO 半满或接收超时时触发中断；发送中断只在发送缓冲区有数据时打开
+        self.registers.IFLS.write(IFLS::RXIFLSEL::OneHalf + IFLS::TXIFLSEL::OneEighth);
+        self.registers.IMSC.write(IMSC::RXIM::SET + IMSC::RTIM::SET);
+    }
+
+    /// Reprogram the UART for `baud`, given the reference clock `uart_clk` in Hz.
+    ///
+    /// Waits for pending output to go out first. Characters in flight on the receiving side are
+    /// lost.
+    pub fn set_baud_rate(&mut self, baud: u32, uart_clk: u32) -> Result<(), &'static str> {
         // 对于波特率的算法见：https://developer.arm.com/documentation/ddi0183/g/programmers-model/register-descriptions/fractional-baud-rate-register--uartfbrd
-        self.registers.IBRD.write(IBRD::BAUD_DIVINT.val(3));
-        self.registers.FBRD.write(FBRD::BAUD_DIVFRAC.val(16));
+        let (int, frac) = baud_divisor(baud, uart_clk)?;
+
+        self.baud = baud;
+        self.uart_clk = uart_clk;
+        self.program(int, frac);
+        Ok(())
+    }
+
+    /// Switch to the frame format and flow control in `line`, keeping the baud rate. Same
+    /// caveats as `set_baud_rate`.
+    pub fn set_line_config(&mut self, line: LineConfig) -> Result<(), &'static str> {
+        let (int, frac) = baud_divisor(self.baud, self.uart_clk)?;
+
+        self.line = line;
+        self.program(int, frac);
+        Ok(())
+    }
+
+    fn program(&mut self, int: u32, frac: u32) {
+        self.flush();
+        // 把CR寄存器置0，UART关闭时才能修改波特率
+        self.registers.CR.set(0);
 
-        // 开启fifo通信并设置通信数据长度为8位
-        // 此处使用9为原因之一是目前芯片串口通信的最大长度就为8位，不需要再做额外处理
-        self.registers.LCR_H.write(LCR_H::WLEN::EightBit + LCR_H::FEN::FifosEnabled);
+        self.registers.IBRD.write(IBRD::BAUD_DIVINT.val(int));
+        self.registers.FBRD.write(FBRD::BAUD_DIVFRAC.val(frac));
 
-        // 开启RXE和TXE功能，对应树莓派的14和15号脚针，并开启UART
-        self.registers.CR.write(CR::RXE::Enabled + CR::TXE::Enabled + CR::UARTEN::Enabled)
+        // 开启fifo通信并设置数据位、校验位和停止位
+        // 写LCR_H同时让新的IBRD和FBRD生效
+        self.registers.LCR_H.write(lcr_h(&self.line));
+
+        // 开启RXE和TXE功能，对应树莓派的14和15号脚针，并开启UART；按配置开启RTS/CTS流控
+        self.registers.CR.write(cr(&self.line));
     }
 
-    pub fn flush(&self) {
+    pub fn flush(&mut self) {
+        // 先把发送缓冲区的数据全部送进 FIFO
+        while !self.tx.is_empty() {
+            self.fill_tx_fifo();
+        }
+
         // 等待FR寄存器的BUSY指示位
         while self.registers.FR.matches_all(FR::BUSY::SET) {
             cpu::nop();
         }
     }
 
-    fn write_char(&mut self, c: char) {
-        while self.registers.FR.matches_all(FR::TXFF::SET) {
-            cpu::nop();
+    /// Move bytes from the TX buffer to the TX FIFO until one of them is full or empty. The
+    /// transmit interrupt stays enabled while bytes are left.
+    fn fill_tx_fifo(&mut self) {
+        while !self.registers.FR.matches_all(FR::TXFF::SET) {
+            match self.tx.pop() {
+                Some(b) => self.registers.DR.set(u32::from(b)),
+                None => break,
+            }
+        }
+
+        if self.tx.is_empty() {
+            self.registers.IMSC.modify(IMSC::TXIM::CLEAR);
+        } else {
+            self.registers.IMSC.modify(IMSC::TXIM::SET);
         }
-        self.registers.DR.set(c as u32);
-        self.chars_written += 1;
     }
 
-    fn read_char_converting(&mut self, blocking_mode: BlockingMode) -> Option<char> {
-        // 查看FR寄存器的RXFE位是否指示DR寄存器存在可读字符
-        if self.registers.FR.matches_all(FR::RXFE::SET) {
-            if blocking_mode == BlockingMode::NonBlocking {
-                return None;
+    /// Move bytes from the RX FIFO to the RX buffer. Bytes that do not fit stay in the FIFO.
+    fn drain_rx_fifo(&mut self) {
+        while !self.rx.is_full() && !self.registers.FR.matches_all(FR::RXFE::SET) {
+            // 从DR寄存器中读出一个字符及其错误标志
+            let dr = self.registers.DR.extract();
+            if dr.get() & !0xFF != 0 {
+                self.count_errors(dr);
             }
-            while self.registers.FR.matches_all(FR::RXFE::SET) {
-                cpu::nop();
+
+            // 出错的字符也交给上层，由协议的校验发现问题，避免数据流错位
+            self.rx.push(dr.read(DR::DATA) as u8);
+        }
+
+        // 溢出时丢掉的字符进不了 FIFO：RSR 立即置 OE，DR 要等下一个进入 FIFO 的字符才标出，
+        // 所以溢出按 RSR 计数
+        let rsr = self.registers.RSRECR.extract();
+        if rsr.get() != 0 {
+            self.errors.overruns += usize::from(rsr.is_set(RSR::OE));
+
+            // 清除RSR中的错误状态
+            self.registers.RSRECR.set(0);
+        }
+    }
+
+    fn count_errors(&mut self, dr: LocalRegisterCopy<u32, DR::Register>) {
+        self.errors.framing += usize::from(dr.is_set(DR::FE));
+        self.errors.parity += usize::from(dr.is_set(DR::PE));
+        self.errors.breaks += usize::from(dr.is_set(DR::BE));
+    }
+
+    /// Queue `b` for sending, or return `false` if the TX buffer is full.
+    fn try_write(&mut self, b: u8) -> bool {
+        if !self.tx.push(b) {
+            self.fill_tx_fifo();
+            if !self.tx.push(b) {
+                return false;
             }
         }
 
-        // 从DR寄存器中读出一个字符
-        let ret = self.registers.DR.get() as u8 as char;
+        self.chars_written += 1;
+        self.fill_tx_fifo();
+        true
+    }
 
-        // if ret == '\r' {
-        //     ret = '\n';
-        // }
+    fn try_read(&mut self) -> Option<u8> {
+        // Whoever waits for an answer must not keep the question in the TX buffer.
+        if !self.tx.is_empty() {
+            self.fill_tx_fifo();
+        }
+        self.drain_rx_fifo();
 
+        let b = self.rx.pop()?;
         self.chars_read += 1;
+        Some(b)
+    }
+
+    fn write_char(&mut self, c: char) {
+        while !self.try_write(c as u8) {
+            cpu::nop();
+        }
+    }
+
+    fn clear_rx(&mut self) {
+        self.rx.clear();
+        while !self.registers.FR.matches_all(FR::RXFE::SET) {
+            self.registers.DR.get();
+        }
+    }
 
-        Some(ret)
+    fn handle_interrupt(&mut self) {
+        let pending = self.registers.MIS.extract();
+
+        if pending.is_set(MIS::RXMIS) || pending.is_set(MIS::RTMIS) {
+            self.drain_rx_fifo();
+        }
+        if pending.is_set(MIS::TXMIS) {
+            self.fill_tx_fifo();
+        }
+
+        // RX 和超时中断在 FIFO 读空后才会撤销，缓冲区满时要清除，否则会一直触发
+        self.registers.ICR.write(ICR::ALL::CLEAR);
     }
 }
 
@@ -210,11 +470,43 @@ impl fmt::Write for PL1011UartInner {
 impl PL1011Uart {
     pub const COMPATIBLE: &'static str = "BCM PL011 UART";
 
-    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+    /// `uart_clk` is the reference clock in Hz, as configured by the firmware.
+    pub const unsafe fn new(mmio_start_addr: usize, uart_clk: u32) -> Self {
         Self {
-            inner: NullLock::new(PL1011UartInner::new(mmio_start_addr))
+            inner: IRQSafeNullLock::new(PL1011UartInner::new(mmio_start_addr, uart_clk))
         }
     }
+
+    /// Switch to `baud`, see `PL1011UartInner::set_baud_rate`.
+    pub fn set_baud_rate(&self, baud: u32, uart_clk: u32) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.set_baud_rate(baud, uart_clk))
+    }
+
+    /// Switch the frame format and flow control, see `PL1011UartInner::set_line_config`.
+    pub fn set_line_config(&self, line: LineConfig) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.set_line_config(line))
+    }
+
+    pub fn line_config(&self) -> LineConfig {
+        self.inner.lock(|inner| inner.line)
+    }
+
+    /// Queue `b` for sending without waiting. Returns `false` if the TX buffer is full.
+    pub fn try_write(&self, b: u8) -> bool {
+        self.inner.lock(|inner| inner.try_write(b))
+    }
+
+    /// The next received byte, if there is one.
+    pub fn try_read(&self) -> Option<u8> {
+        self.inner.lock(|inner| inner.try_read())
+    }
+
+    /// Move data between the FIFOs and the buffers. Called by the handler of the UART interrupt.
+    /// On boards without a driven interrupt controller the other calls do the same work when
+    /// they run.
+    pub fn handle_interrupt(&self) {
+        self.inner.lock(|inner| inner.handle_interrupt())
+    }
 }
 
 impl DeviceDriver for PL1011Uart {
@@ -227,24 +519,52 @@ impl DeviceDriver for PL1011Uart {
 
         Ok(())
     }
+
+    fn register_and_enable_irq_handler(
+        &'static self,
+        irq_number: &IRQNumber,
+    ) -> Result<(), &'static str> {
+        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self);
+
+        irq_manager().register_handler(descriptor)?;
+        irq_manager().enable(irq_number);
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for PL1011Uart {
+    fn handle(&self) -> Result<(), &'static str> {
+        self.handle_interrupt();
+
+        Ok(())
+    }
 }
 
 impl console::interface::Read for PL1011Uart {
-    fn read_char(&self) -> char {
-        self.inner.lock(|inner| inner.read_char_converting(BlockingMode::Blocking).unwrap())
+    // The lock is dropped between polls, so the UART interrupt can fill the RX buffer.
+    fn read_char_timeout(&self, timeout: Duration) -> Option<char> {
+        let deadline = time::time_manager().uptime() + timeout;
+
+        loop {
+            let c = self.try_read().map(char::from);
+            if c.is_some() || time::time_manager().uptime() >= deadline {
+                return c;
+            }
+        }
     }
 
     fn clear_rx(&self) {
-        while self.inner
-            .lock(|inner| inner.read_char_converting(BlockingMode::NonBlocking))
-            .is_some()
-        {}
+        self.inner.lock(|inner| inner.clear_rx())
     }
 }
 
 impl console::interface::Write for PL1011Uart {
+    // The lock is dropped while the TX buffer is full, so the UART interrupt can drain it.
     fn write_char(&self, c: char) {
-        self.inner.lock(|inner| inner.write_char(c));
+        while !self.try_write(c as u8) {
+            cpu::nop();
+        }
     }
 
     fn write_fmt(&self, arg: Arguments) -> fmt::Result {
@@ -264,8 +584,24 @@ impl console::interface::Statistics for PL1011Uart {
     fn chars_read(&self) -> usize {
         self.inner.lock(|inner| inner.chars_read)
     }
+
+    fn framing_errors(&self) -> usize {
+        self.inner.lock(|inner| inner.errors.framing)
+    }
+
+    fn parity_errors(&self) -> usize {
+        self.inner.lock(|inner| inner.errors.parity)
+    }
+
+    fn break_conditions(&self) -> usize {
+        self.inner.lock(|inner| inner.errors.breaks)
+    }
+
+    fn overrun_errors(&self) -> usize {
+        self.inner.lock(|inner| inner.errors.overruns)
+    }
 }
 
 impl console::interface::All for PL1011Uart {
 
-}
\ No newline at end of file
+}
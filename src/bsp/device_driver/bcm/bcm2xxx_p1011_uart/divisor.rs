@@ -0,0 +1,47 @@
+//! Baud rate divisor, kept apart from the registers so the host can test it.
+
+/// Split the divisor `uart_clk / (16 * baud)` into the IBRD and FBRD values.
+///
+/// The fractional part has 6 bits, so the divisor is computed in 1/64 steps and rounded.
+pub fn baud_divisor(baud: u32, uart_clk: u32) -> Result<(u32, u32), &'static str> {
+    if baud == 0 {
+        return Err("baud rate must not be zero");
+    }
+
+    let div_x64 = (4 * u64::from(uart_clk) + u64::from(baud) / 2) / u64::from(baud);
+    let (int, frac) = ((div_x64 >> 6) as u32, (div_x64 & 0x3F) as u32);
+
+    // IBRD is 16 bits wide, and 0xFFFF only goes with FBRD 0.
+    if int == 0 || div_x64 > 0xFFFF << 6 {
+        return Err("baud rate not reachable with this UART clock");
+    }
+
+    Ok((int, frac))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::baud_divisor;
+
+    const UART_CLK: u32 = 48_000_000;
+
+    #[test]
+    fn divisor_is_rounded_to_a_sixty_fourth() {
+        // Exactly 26.042 and 3.255.
+        assert_eq!(baud_divisor(115_200, UART_CLK), Ok((26, 3)));
+        assert_eq!(baud_divisor(921_600, UART_CLK), Ok((3, 16)));
+        assert_eq!(baud_divisor(3_000_000, UART_CLK), Ok((1, 0)));
+    }
+
+    #[test]
+    fn refuses_rates_out_of_reach() {
+        assert!(baud_divisor(0, UART_CLK).is_err());
+
+        // Faster than UART_CLK / 16.
+        assert!(baud_divisor(3_100_000, UART_CLK).is_err());
+
+        // A divisor above 0xFFFF.
+        assert!(baud_divisor(45, UART_CLK).is_err());
+        assert_eq!(baud_divisor(46, UART_CLK), Ok((65217, 25)));
+    }
+}
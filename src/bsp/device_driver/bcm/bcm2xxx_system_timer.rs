@@ -0,0 +1,82 @@
+use core::time::Duration;
+use tock_registers::{register_structs, registers::ReadOnly};
+use tock_registers::interfaces::Readable;
+use crate::bsp::device_driver::common::MMIODerefWrapper;
+use crate::driver::interface::DeviceDriver;
+use crate::synchronization::interface::Mutex;
+use crate::synchronization::IRQSafeNullLock;
+use crate::time;
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        /// Low and high word of the free-running 1 MHz counter.
+        (0x04 => CLO: ReadOnly<u32>),
+        (0x08 => CHI: ReadOnly<u32>),
+        (0x0c => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// The counter runs at 1 MHz whatever the core clock.
+const TICKS_PER_SEC: u64 = 1_000_000;
+
+struct SystemTimerInner {
+    registers: Registers,
+}
+
+/// The BCM system timer: a 64-bit 1 MHz counter. Only the counter is driven, the loader waits
+/// by polling `time::time_manager`. The compare channels after it are left to the GPU and the
+/// payload.
+pub struct SystemTimer {
+    inner: IRQSafeNullLock<SystemTimerInner>,
+}
+
+impl SystemTimerInner {
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+        }
+    }
+
+    fn ticks(&self) -> u64 {
+        // CLO may wrap between reading the words. Read CHI again to catch that.
+        loop {
+            let high = self.registers.CHI.get();
+            let low = self.registers.CLO.get();
+            if self.registers.CHI.get() == high {
+                return u64::from(high) << 32 | u64::from(low);
+            }
+        }
+    }
+}
+
+impl SystemTimer {
+    pub const COMPATIBLE: &'static str = "BCM System Timer";
+
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(SystemTimerInner::new(mmio_start_addr))
+        }
+    }
+}
+
+impl DeviceDriver for SystemTimer {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+}
+
+impl time::interface::TimeManager for SystemTimer {
+    fn resolution(&self) -> Duration {
+        Duration::from_micros(1)
+    }
+
+    fn uptime(&self) -> Duration {
+        let ticks = self.inner.lock(|inner| inner.ticks());
+
+        Duration::new(ticks / TICKS_PER_SEC, (ticks % TICKS_PER_SEC * 1_000) as u32)
+    }
+}
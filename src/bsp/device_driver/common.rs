@@ -22,4 +22,160 @@ impl<T> ops::Deref for MMIODerefWrapper<T> {
             &*(self.start_addr as *const _)
         }
     }
-}
\ No newline at end of file
+}
+
+/// A fixed-size FIFO of bytes. Full buffers refuse new bytes.
+#[cfg(not(feature = "mini-uart"))]
+pub struct RingBuffer<const N: usize> {
+    data: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+#[cfg(not(feature = "mini-uart"))]
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            data: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Append `byte`, or return `false` if there is no room.
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+
+        self.data[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let byte = self.data[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+/// Baud rate the UARTs start with, which is what the host tools expect.
+pub const DEFAULT_BAUD_RATE: u32 = 921_600;
+
+/// Character width. Nothing asks for less than 8 bits yet: the loader protocol is binary.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// Frame format and flow control of a UART line.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct LineConfig {
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    /// RTS/CTS. The BSP has to route the CTS and RTS pins to the UART as well.
+    pub flow_control: bool,
+}
+
+impl LineConfig {
+    /// 8N1 without flow control, which is what the host tools expect.
+    pub const DEFAULT: Self = Self {
+        data_bits: DataBits::Eight,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+        flow_control: false,
+    };
+}
+
+#[cfg(all(test, not(feature = "mini-uart")))]
+mod tests {
+    use super::RingBuffer;
+
+    #[test]
+    fn pop_returns_bytes_in_push_order() {
+        let mut buffer = RingBuffer::<4>::new();
+        assert!(buffer.is_empty());
+        assert!(buffer.push(1));
+        assert!(buffer.push(2));
+
+        assert_eq!(buffer.pop(), Some(1));
+        assert_eq!(buffer.pop(), Some(2));
+        assert_eq!(buffer.pop(), None);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn full_buffer_refuses_bytes() {
+        let mut buffer = RingBuffer::<2>::new();
+        assert!(buffer.push(1));
+        assert!(buffer.push(2));
+        assert!(buffer.is_full());
+
+        assert!(!buffer.push(3));
+        assert_eq!(buffer.pop(), Some(1));
+        assert_eq!(buffer.pop(), Some(2));
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn wraps_around_the_end() {
+        let mut buffer = RingBuffer::<3>::new();
+        for round in 0..10u8 {
+            assert!(buffer.push(round));
+            assert!(buffer.push(round.wrapping_add(100)));
+            assert_eq!(buffer.pop(), Some(round));
+            assert_eq!(buffer.pop(), Some(round.wrapping_add(100)));
+        }
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn clear_empties_the_buffer() {
+        let mut buffer = RingBuffer::<3>::new();
+        buffer.push(1);
+        buffer.push(2);
+        buffer.pop();
+        buffer.clear();
+
+        assert!(buffer.is_empty());
+        assert!(buffer.push(3));
+        assert_eq!(buffer.pop(), Some(3));
+    }
+}
@@ -1,7 +1,11 @@
 pub mod cpu;
 pub mod console;
 pub mod driver;
+pub mod exception;
+pub mod gpio;
 pub mod memory;
+#[cfg(feature = "system-timer")]
+pub mod time;
 
 pub fn board_name() -> &'static str {
     #[cfg(feature = "bsp-rpi-3")]
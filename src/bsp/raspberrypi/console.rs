@@ -2,4 +2,49 @@
 
 // pub fn console() -> &'static dyn console::interface::All {
 //     &super::driver::PL1011_UART
-// }
\ No newline at end of file
+// }
+
+pub use crate::bsp::device_driver::{DataBits, LineConfig, Parity, StopBits, DEFAULT_BAUD_RATE};
+
+/// Switch the console UART to `baud`.
+#[cfg(not(feature = "mini-uart"))]
+pub fn set_baud_rate(baud: u32) -> Result<(), &'static str> {
+    super::driver::PL1011_UART.set_baud_rate(baud, super::driver::PL1011_UART_CLOCK)
+}
+
+/// Switch the console UART to `baud`.
+#[cfg(feature = "mini-uart")]
+pub fn set_baud_rate(baud: u32) -> Result<(), &'static str> {
+    super::driver::MINI_UART.set_baud_rate(baud, super::driver::MINI_UART_CLOCK)
+}
+
+
+/// Switch the console UART to the frame format and flow control in `line`. Flow control takes
+/// GPIO16 (CTS) and GPIO17 (RTS).
+#[cfg(not(feature = "mini-uart"))]
+pub fn set_line_config(line: LineConfig) -> Result<(), &'static str> {
+    use super::driver::{GPIO, PL1011_UART};
+
+    let flow_control = PL1011_UART.line_config().flow_control;
+    // The pins change over while the UART does not use them.
+    if line.flow_control && !flow_control {
+        GPIO.map_p1011_flow_control(true)?;
+    }
+    PL1011_UART.set_line_config(line)?;
+    if !line.flow_control && flow_control {
+        GPIO.map_p1011_flow_control(false)?;
+    }
+
+    Ok(())
+}
+
+/// Switch the console UART to the frame format and flow control in `line`. The mini UART is left
+/// at 8N1 without flow control.
+#[cfg(feature = "mini-uart")]
+pub fn set_line_config(line: LineConfig) -> Result<(), &'static str> {
+    if line != LineConfig::DEFAULT {
+        return Err("the mini UART only supports 8N1 without flow control");
+    }
+
+    Ok(())
+}
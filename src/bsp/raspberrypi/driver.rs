@@ -1,41 +1,126 @@
 use core::sync::atomic::{AtomicBool, Ordering};
 use crate::bsp::device_driver;
 use crate::{console, driver};
+use super::exception::asynchronous::irq_map;
+
+/// PL011 reference clock, as set by `init_uart_clock` in the firmware's config.txt.
+#[cfg(not(feature = "mini-uart"))]
+pub(super) const PL1011_UART_CLOCK: u32 = 48_000_000;
+
+#[cfg(not(feature = "mini-uart"))]
+pub(super) static PL1011_UART: device_driver::PL1011Uart = unsafe {
+    device_driver::PL1011Uart::new(super::memory::map::mmio::UART_START, PL1011_UART_CLOCK)
+};
+
+/// VPU core clock the mini UART runs from, as fixed by `enable_uart=1` in the firmware's
+/// config.txt.
+#[cfg(all(feature = "mini-uart", feature = "bsp-rpi-3"))]
+pub(super) const MINI_UART_CLOCK: u32 = 250_000_000;
+#[cfg(all(feature = "mini-uart", feature = "bsp-rpi-4"))]
+pub(super) const MINI_UART_CLOCK: u32 = 500_000_000;
+
+#[cfg(feature = "mini-uart")]
+pub(super) static MINI_UART: device_driver::MiniUart = unsafe {
+    device_driver::MiniUart::new(super::memory::map::mmio::AUX_START, MINI_UART_CLOCK)
+};
 
-pub(super) static PL1011_UART: device_driver::PL1011Uart =
-    unsafe { device_driver::PL1011Uart::new(super::memory::map::mmio::UART_START) };
 pub(super) static GPIO: device_driver::GPIO =
     unsafe { device_driver::GPIO::new(super::memory::map::mmio::GPIO_START) };
+#[cfg(feature = "bsp-rpi-3")]
+pub(super) static INTERRUPT_CONTROLLER: device_driver::InterruptController = unsafe {
+    device_driver::InterruptController::new(
+        super::memory::map::mmio::LOCAL_IC_START,
+        super::memory::map::mmio::PERIPHERAL_IC_START,
+    )
+};
+#[cfg(feature = "bsp-rpi-4")]
+pub(super) static INTERRUPT_CONTROLLER: device_driver::GICv2 = unsafe {
+    device_driver::GICv2::new(
+        super::memory::map::mmio::GICD_START,
+        super::memory::map::mmio::GICC_START,
+    )
+};
+
+#[cfg(feature = "system-timer")]
+pub(super) static SYSTEM_TIMER: device_driver::SystemTimer =
+    unsafe { device_driver::SystemTimer::new(super::memory::map::mmio::SYSTEM_TIMER_START) };
 
+fn post_init_interrupt_controller() -> Result<(), &'static str> {
+    crate::exception::asynchronous::register_irq_manager(&INTERRUPT_CONTROLLER);
+    Ok(())
+}
+
+#[cfg(not(feature = "mini-uart"))]
 fn post_init_uart() -> Result<(), &'static str> {
     console::register_console(&PL1011_UART);
     Ok(())
 }
 
+#[cfg(feature = "mini-uart")]
+fn post_init_uart() -> Result<(), &'static str> {
+    console::register_console(&MINI_UART);
+    Ok(())
+}
+
 fn post_init_gpio() -> Result<(), &'static str> {
+    #[cfg(not(feature = "mini-uart"))]
     GPIO.map_p1011_uart();
+    #[cfg(feature = "mini-uart")]
+    GPIO.map_mini_uart();
+    Ok(())
+}
+
+/// Registered first so that the IRQ manager is in place before any driver asks for its
+/// interrupt.
+fn driver_interrupt_controller() -> Result<(), &'static str> {
+    let interrupt_controller_descriptor = driver::DeviceDriverDescriptor::new(
+        &INTERRUPT_CONTROLLER,
+        Some(post_init_interrupt_controller),
+        None,
+    );
+    driver::driver_manager().register_driver(interrupt_controller_descriptor);
     Ok(())
 }
 
+/// Only the console UART is driven, the other one is left to the payload.
 fn driver_uart() -> Result<(), &'static str> {
-    let uart_descriptor = driver::DeviceDriverDescriptor::new(&PL1011_UART, Some(post_init_uart));
+    #[cfg(not(feature = "mini-uart"))]
+    let (uart, irq_number): (&'static (dyn driver::interface::DeviceDriver + Sync), _) =
+        (&PL1011_UART, irq_map::PL011_UART);
+    // 迷你串口没有使用中断
+    #[cfg(feature = "mini-uart")]
+    let (uart, irq_number): (&'static (dyn driver::interface::DeviceDriver + Sync), _) =
+        (&MINI_UART, None);
+
+    let uart_descriptor =
+        driver::DeviceDriverDescriptor::new(uart, Some(post_init_uart), irq_number);
     driver::driver_manager().register_driver(uart_descriptor);
     Ok(())
 }
 
 fn driver_gpio() -> Result<(), &'static str> {
-    let gpio_descriptor = driver::DeviceDriverDescriptor::new(&GPIO, Some(post_init_gpio));
+    let gpio_descriptor = driver::DeviceDriverDescriptor::new(&GPIO, Some(post_init_gpio), irq_map::GPIO);
     driver::driver_manager().register_driver(gpio_descriptor);
     Ok(())
 }
 
+#[cfg(feature = "system-timer")]
+fn driver_system_timer() -> Result<(), &'static str> {
+    let timer_descriptor = driver::DeviceDriverDescriptor::new(&SYSTEM_TIMER, None, None);
+    driver::driver_manager().register_driver(timer_descriptor);
+    Ok(())
+}
+
 pub unsafe fn init() -> Result<(), &'static str> {
     static INIT_DONE: AtomicBool = AtomicBool::new(false);
     if INIT_DONE.load(Ordering::Relaxed) {
         return Err("Init already done");
     }
+    driver_interrupt_controller()?;
     driver_uart()?;
     driver_gpio()?;
+    #[cfg(feature = "system-timer")]
+    driver_system_timer()?;
     INIT_DONE.store(true, Ordering::Relaxed);
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,27 @@
+//! The board's interrupt numbers.
+
+pub type IRQNumber = crate::bsp::device_driver::IRQNumber;
+
+/// The interrupts the drivers handle.
+#[cfg(feature = "bsp-rpi-3")]
+pub(in crate::bsp) mod irq_map {
+    use super::IRQNumber;
+    use crate::bsp::device_driver::PeripheralIRQ;
+
+    /// gpio_int[0]: events on GPIO0..=27, which includes all pins of the header.
+    pub const GPIO: Option<IRQNumber> = Some(IRQNumber::Peripheral(PeripheralIRQ::new(49)));
+    #[cfg(not(feature = "mini-uart"))]
+    pub const PL011_UART: Option<IRQNumber> = Some(IRQNumber::Peripheral(PeripheralIRQ::new(57)));
+}
+
+/// The interrupts the drivers handle. The VideoCore peripherals' interrupts are SPIs from ID 96
+/// on, in the order of the legacy controller.
+#[cfg(feature = "bsp-rpi-4")]
+pub(in crate::bsp) mod irq_map {
+    use super::IRQNumber;
+
+    /// gpio_int[0]: events on GPIO0..=27, which includes all pins of the header.
+    pub const GPIO: Option<IRQNumber> = Some(IRQNumber::new(96 + 49));
+    #[cfg(not(feature = "mini-uart"))]
+    pub const PL011_UART: Option<IRQNumber> = Some(IRQNumber::new(96 + 57));
+}
@@ -0,0 +1,7 @@
+pub use crate::bsp::device_driver::{mode, Event, Function, Pin, Pull};
+
+/// Take ownership of GPIO pin `number`, see `device_driver::GPIO::claim`. GPIO14 and GPIO15 belong
+/// to the console UART.
+pub fn claim(number: u8) -> Result<Pin<mode::Unconfigured>, &'static str> {
+    super::driver::GPIO.claim(number)
+}
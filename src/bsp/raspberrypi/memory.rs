@@ -1,16 +1,47 @@
+use core::cell::UnsafeCell;
+use core::ops::Range;
+
+// Symbols from the linker script.
+extern "Rust" {
+    static __boot_core_stack_start: UnsafeCell<()>;
+    static __binary_nonzero_start: UnsafeCell<()>;
+    static __bss_end_exclusive: UnsafeCell<()>;
+}
+
 pub(super) mod map {
     pub const BOARD_DEFAULT_LOAD_ADDRESS: usize = 0x8_0000;
 
+    /// End of the RAM the ARM cores get in the first GiB with the firmware's default split, which
+    /// leaves up to 76 MiB to the GPU. Only assumed without a device tree that tells.
+    pub const ARM_MEMORY_END: usize = 0x3B40_0000;
+
+    #[cfg(feature = "system-timer")]
+    pub const SYSTEM_TIMER_OFFSET: usize = 0x0000_3000;
+    #[cfg(feature = "bsp-rpi-3")]
+    pub const PERIPHERAL_IC_OFFSET: usize = 0x0000_B200;
     pub const GPIO_OFFSET: usize = 0x0020_0000;
+    #[cfg(not(feature = "mini-uart"))]
     pub const UART_OFFSET: usize = 0x0020_1000;
+    #[cfg(feature = "mini-uart")]
+    pub const AUX_OFFSET: usize = 0x0021_5000;
 
     #[cfg(feature = "bsp-rpi-3")]
     pub mod mmio {
         use super::*;
 
         pub const START: usize = 0x3F00_0000;
+        /// Everything from here up is peripherals.
+        pub const DEVICE_START: usize = START;
+        #[cfg(feature = "system-timer")]
+        pub const SYSTEM_TIMER_START: usize = START + SYSTEM_TIMER_OFFSET;
+        pub const PERIPHERAL_IC_START: usize = START + PERIPHERAL_IC_OFFSET;
+        /// The per-core peripherals (local interrupt controller, core timers, mailboxes).
+        pub const LOCAL_IC_START: usize = 0x4000_0000;
         pub const GPIO_START: usize = START + GPIO_OFFSET;
+        #[cfg(not(feature = "mini-uart"))]
         pub const UART_START: usize = START + UART_OFFSET;
+        #[cfg(feature = "mini-uart")]
+        pub const AUX_START: usize = START + AUX_OFFSET;
     }
 
     #[cfg(feature = "bsp-rpi-4")]
@@ -18,12 +49,64 @@ pub(super) mod map {
         use super::*;
 
         pub const START: usize = 0xFE00_0000;
+        /// Everything from here up is peripherals.
+        pub const DEVICE_START: usize = 0xFC00_0000;
+        #[cfg(feature = "system-timer")]
+        pub const SYSTEM_TIMER_START: usize = START + SYSTEM_TIMER_OFFSET;
+        /// The GIC-400's distributor and CPU interface.
+        pub const GICD_START: usize = 0xFF84_1000;
+        pub const GICC_START: usize = 0xFF84_2000;
         pub const GPIO_START: usize = START + GPIO_OFFSET;
+        #[cfg(not(feature = "mini-uart"))]
         pub const UART_START: usize = START + UART_OFFSET;
+        #[cfg(feature = "mini-uart")]
+        pub const AUX_START: usize = START + AUX_OFFSET;
     }
 }
 
 #[inline(always)]
 pub fn board_default_load_addr() -> *const u64 {
     map::BOARD_DEFAULT_LOAD_ADDRESS as _
-}
\ No newline at end of file
+}
+
+/// Where the device memory starts. The MMU maps everything below as RAM, and everything from
+/// here up to 4 GiB as device memory.
+pub fn device_memory_start() -> usize {
+    map::mmio::DEVICE_START
+}
+
+/// Lowest address used by the relocated loader.
+///
+/// The boot core stack ends where the binary starts (`__boot_core_stack_end_exclusive` ==
+/// `__binary_nonzero_start`) and grows down to this address.
+#[inline(always)]
+fn loader_start() -> usize {
+    unsafe { __boot_core_stack_start.get() as usize }
+}
+
+/// The DRAM range a payload can be loaded to without overwriting the loader or its stack.
+pub fn load_window() -> Range<usize> {
+    map::BOARD_DEFAULT_LOAD_ADDRESS..loader_start()
+}
+
+/// The DRAM range above the loader, for payloads that do not fit below. It ends at `ram_end`,
+/// the end of the RAM bank the loader is in, if known.
+pub fn high_window(ram_end: Option<usize>) -> Range<usize> {
+    let start = loader_footprint().end;
+    start..usize::max(start, ram_end.unwrap_or(map::ARM_MEMORY_END))
+}
+
+/// Where a payload branches to in order to get back into the loader: the second instruction of
+/// the relocated binary, see `_start`.
+///
+/// The payload must leave `loader_footprint` alone, branch at the exception level it was started
+/// at, with the MMU and data cache off, and pass `cpu::boot::REENTRY_MAGIC` in x0 and the device
+/// tree (or 0) in x1.
+pub fn reentry_vector() -> usize {
+    unsafe { __binary_nonzero_start.get() as usize + 4 }
+}
+
+/// The DRAM range occupied by the relocated loader, including its stack and BSS.
+pub fn loader_footprint() -> Range<usize> {
+    loader_start()..unsafe { __bss_end_exclusive.get() as usize }
+}
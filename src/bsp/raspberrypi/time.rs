@@ -0,0 +1,6 @@
+use crate::time::interface::TimeManager;
+
+/// The BCM system timer, which `time::time_manager` returns with the `system-timer` feature.
+pub fn system_timer() -> &'static dyn TimeManager {
+    &super::driver::SYSTEM_TIMER
+}
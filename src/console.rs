@@ -1,8 +1,9 @@
-use crate::synchronization::interface::Mutex;
-use crate::synchronization::NullLock;
+use crate::synchronization::interface::ReadWriteEx;
+use crate::synchronization::ReadWriteExclusive;
 
 pub mod interface {
     use core::fmt;
+    use core::time::Duration;
 
     pub trait Write {
         fn write_char(&self, c: char);
@@ -13,13 +14,23 @@ pub mod interface {
     }
 
     pub trait Read {
-        fn read_char(&self) -> char { ' ' }
+        /// Wait up to `timeout` for a character. `None` if none arrived.
+        fn read_char_timeout(&self, _timeout: Duration) -> Option<char> { None }
         fn clear_rx(&self);
     }
 
+    /// Counted since the UART was initialised: since boot, or since a payload came back into
+    /// the loader.
     pub trait Statistics {
         fn chars_written(&self) -> usize { 0 }
         fn chars_read(&self) -> usize { 0 }
+        /// Characters received without a valid stop bit, typically from mismatched baud rates.
+        fn framing_errors(&self) -> usize { 0 }
+        fn parity_errors(&self) -> usize { 0 }
+        /// Times the line was held low for longer than a frame, e.g. by a loose cable.
+        fn break_conditions(&self) -> usize { 0 }
+        /// Times characters were lost because the receive FIFO was full.
+        fn overrun_errors(&self) -> usize { 0 }
     }
 
     pub trait All: Write + Read + Statistics {}
@@ -27,14 +38,14 @@ pub mod interface {
 
 mod null_console;
 
-static CURR_CONSOLE: NullLock<&'static (dyn interface::All + Sync)> = NullLock::new(&null_console::NULL_CONSOLE);
+static CURR_CONSOLE: ReadWriteExclusive<&'static (dyn interface::All + Sync)> = ReadWriteExclusive::new(&null_console::NULL_CONSOLE);
 
 pub fn console() -> &'static dyn interface::All {
-    CURR_CONSOLE.lock(|con| *con)
+    CURR_CONSOLE.read(|con| *con)
 }
 
 pub fn register_console(console: &'static (dyn interface::All + Sync)) {
-    CURR_CONSOLE.lock(|con| {
+    CURR_CONSOLE.write(|con| {
         *con = console
     })
 }
@@ -1,11 +1,19 @@
-mod boot;
+pub mod boot;
 
 #[path = "./_arch/aarch64/cpu.rs"]
 mod aarch_cpu;
 
 pub use aarch_cpu::wait_forever;
 
-#[cfg(feature = "bsp-rpi-3")]
-pub use aarch_cpu::spin_for_cycles;
+pub use aarch_cpu::nop;
 
-pub use aarch_cpu::nop;
\ No newline at end of file
+pub use aarch_cpu::current_privilege_level;
+
+/// Privilege levels, named after what usually runs at them.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PrivilegeLevel {
+    User,
+    Kernel,
+    Hypervisor,
+    Unknown,
+}
\ No newline at end of file
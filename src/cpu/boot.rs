@@ -1,4 +1,24 @@
 
 #[cfg(target_arch = "aarch64")]
 #[path = "../_arch/aarch64/cpu/boot.rs"]
-mod arrch_boot;
\ No newline at end of file
+mod arrch_boot;
+
+/// Value a payload passes in x0 when it branches to the re-entry vector (see
+/// `bsp::memory::reentry_vector`). The word right behind the vector holds it as well, so that a
+/// payload can check the loader is still there.
+pub const REENTRY_MAGIC: u64 = u64::from_le_bytes(*b"MiniLoad");
+
+/// Argument registers x0..x3 as the firmware left them when it jumped to `_start`.
+///
+/// The firmware follows the Linux arm64 boot protocol: x0 holds the physical address of the
+/// device tree blob, x1..x3 are zero.
+#[derive(Clone, Copy)]
+pub struct BootArgs {
+    pub x0: u64,
+    pub x1: u64,
+    pub x2: u64,
+    pub x3: u64,
+    /// A payload came back through the re-entry vector instead. x0 is the device tree it handed
+    /// back, x1..x3 are zero.
+    pub reentered: bool,
+}
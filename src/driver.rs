@@ -1,16 +1,32 @@
+use crate::exception::asynchronous::IRQNumber;
 use crate::println;
-use crate::synchronization::interface::Mutex;
-use crate::synchronization::NullLock;
+use crate::synchronization::interface::ReadWriteEx;
+use crate::synchronization::ReadWriteExclusive;
 
 const NUM_DRIVERS: usize = 5;
 
 pub mod interface {
+    use crate::exception::asynchronous::IRQNumber;
+
     pub trait DeviceDriver {
         fn compatible(&self) -> &'static str;
 
         unsafe fn init(&self) -> Result<(), &'static str> {
             Ok(())
         }
+
+        /// Register the driver's handler for `irq_number` with the IRQ manager and enable the
+        /// interrupt. Only called for drivers registered with an IRQ number.
+        fn register_and_enable_irq_handler(
+            &'static self,
+            irq_number: &IRQNumber,
+        ) -> Result<(), &'static str> {
+            panic!(
+                "Attempt to enable IRQ {} for device {}, but driver does not support this",
+                irq_number,
+                self.compatible()
+            )
+        }
     }
 }
 
@@ -26,10 +42,11 @@ pub type DeviceDriverPostInitCallback = unsafe fn() -> Result<(), &'static str>;
 pub struct DeviceDriverDescriptor {
     device_driver: &'static (dyn interface::DeviceDriver + Sync),
     post_init_callback: Option<DeviceDriverPostInitCallback>,
+    irq_number: Option<IRQNumber>,
 }
 
 pub struct DriverManager {
-    inner: NullLock<DriverManagerInner>,
+    inner: ReadWriteExclusive<DriverManagerInner>,
 }
 
 impl DriverManagerInner {
@@ -45,8 +62,9 @@ impl DeviceDriverDescriptor {
     pub fn new(
         device_driver: &'static (dyn interface::DeviceDriver + Sync),
         post_init_callback: Option<DeviceDriverPostInitCallback>,
+        irq_number: Option<IRQNumber>,
     ) -> Self {
-        Self { device_driver, post_init_callback }
+        Self { device_driver, post_init_callback, irq_number }
     }
 }
 
@@ -59,19 +77,19 @@ pub fn driver_manager() -> &'static DriverManager {
 impl DriverManager {
     pub const fn new() -> Self {
         Self {
-            inner: NullLock::new(DriverManagerInner::new())
+            inner: ReadWriteExclusive::new(DriverManagerInner::new())
         }
     }
 
     pub fn register_driver(&self, descriptor: DeviceDriverDescriptor) {
-        self.inner.lock(|inner| {
+        self.inner.write(|inner| {
             inner.descriptors[inner.next_index] = Some(descriptor);
             inner.next_index += 1;
         })
     }
 
     fn for_each_descriptor<'a>(&'a self, f: impl FnMut(&'a DeviceDriverDescriptor)) {
-        self.inner.lock(|inner| {
+        self.inner.read(|inner| {
             inner.descriptors
                 .iter()
                 .filter_map(|x| x.as_ref())
@@ -100,6 +118,19 @@ impl DriverManager {
             }
         });
 
+        // 所有驱动初始化完成后（中断控制器也已注册）再挂载中断处理函数
+        self.for_each_descriptor(|descriptor| {
+            if let Some(irq_number) = &descriptor.irq_number {
+                if let Err(x) = descriptor.device_driver.register_and_enable_irq_handler(irq_number) {
+                    panic!(
+                        "Error during driver interrupt handler registration: {}: {}",
+                        descriptor.device_driver.compatible(),
+                        x
+                    );
+                }
+            }
+        });
+
         self.enumerate();
     }
 
@@ -0,0 +1,11 @@
+//! Synchronous and asynchronous exceptions.
+//!
+//! Until something handles them, every exception that reaches the loader prints the saved
+//! context and panics.
+
+#[path = "./_arch/aarch64/exception.rs"]
+mod aarch_exception;
+
+pub mod asynchronous;
+
+pub use aarch_exception::handling_init;
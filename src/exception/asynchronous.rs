@@ -0,0 +1,225 @@
+//! Asynchronous exceptions: IRQs, their handlers and the controller that routes them.
+
+#[path = "../_arch/aarch64/exception/asynchronous.rs"]
+mod aarch_asynchronous;
+
+mod null_irq_manager;
+
+use core::{fmt, marker::PhantomData};
+use crate::synchronization::interface::ReadWriteEx;
+use crate::synchronization::ReadWriteExclusive;
+
+pub use aarch_asynchronous::{
+    is_local_irq_masked, local_irq_mask, local_irq_mask_save, local_irq_restore,
+    local_irq_unmask, print_state,
+};
+
+/// The IRQ numbers of the board's interrupt controller.
+pub type IRQNumber = crate::bsp::exception::asynchronous::IRQNumber;
+
+/// Interrupt descriptor.
+#[derive(Copy, Clone)]
+pub struct IRQHandlerDescriptor<T>
+where
+    T: Copy,
+{
+    /// The IRQ number.
+    number: T,
+
+    /// Descriptive name.
+    name: &'static str,
+
+    /// Reference to handler trait object.
+    handler: &'static (dyn interface::IRQHandler + Sync),
+}
+
+/// IRQContext token.
+///
+/// An instance of this type indicates that the local core is currently executing in IRQ
+/// context, aka executing an interrupt vector or subcalls of it.
+///
+/// Concept and implementation derived from the `CriticalSection` introduced in
+/// <https://github.com/rust-embedded/bare-metal>
+#[derive(Clone, Copy)]
+pub struct IRQContext<'irq_context> {
+    _0: PhantomData<&'irq_context ()>,
+}
+
+pub mod interface {
+    /// Implemented by types that handle IRQs.
+    pub trait IRQHandler {
+        /// Called when the corresponding interrupt is asserted.
+        fn handle(&self) -> Result<(), &'static str>;
+    }
+
+    /// IRQ management functions.
+    ///
+    /// The `BSP` is supposed to supply one global instance. Typically implemented by the
+    /// platform's interrupt controller.
+    pub trait IRQManager {
+        /// The IRQ number type depends on the implementation.
+        type IRQNumberType: Copy;
+
+        /// Register a handler.
+        fn register_handler(
+            &self,
+            irq_handler_descriptor: super::IRQHandlerDescriptor<Self::IRQNumberType>,
+        ) -> Result<(), &'static str>;
+
+        /// Enable an interrupt in the controller.
+        fn enable(&self, irq_number: &Self::IRQNumberType);
+
+        /// Disable all interrupts in the controller, e.g. before handing the board to a payload.
+        fn disable_all(&self) {}
+
+        /// Set the priority of an interrupt. Lower values win.
+        ///
+        /// The loader itself handles all interrupts on the boot core, in the default priority,
+        /// so it calls none of these three.
+        #[allow(dead_code)]
+        fn set_priority(
+            &self,
+            _irq_number: &Self::IRQNumberType,
+            _priority: u8,
+        ) -> Result<(), &'static str> {
+            Err("interrupt priorities not supported")
+        }
+
+        /// Route an interrupt to the cores in `cpu_mask`, bit n standing for core n.
+        #[allow(dead_code)]
+        fn set_target(
+            &self,
+            _irq_number: &Self::IRQNumberType,
+            _cpu_mask: u8,
+        ) -> Result<(), &'static str> {
+            Err("interrupt routing not supported")
+        }
+
+        /// Raise a software generated interrupt on the cores in `cpu_mask`.
+        #[allow(dead_code)]
+        fn send_sgi(
+            &self,
+            _irq_number: &Self::IRQNumberType,
+            _cpu_mask: u8,
+        ) -> Result<(), &'static str> {
+            Err("software generated interrupts not supported")
+        }
+
+        /// Handle pending interrupts.
+        ///
+        /// This function is called directly from the CPU's IRQ exception vector. On AArch64,
+        /// this means that the respective CPU core has disabled exception handling.
+        /// This function can therefore not be preempted and runs start to finish.
+        ///
+        /// Takes an IRQContext token to ensure it can only be called from IRQ context.
+        #[allow(clippy::trivially_copy_pass_by_ref)]
+        fn handle_pending_irqs<'irq_context>(
+            &'irq_context self,
+            ic: &super::IRQContext<'irq_context>,
+        );
+
+        /// Print list of registered handlers.
+        fn print_handler(&self) {}
+    }
+}
+
+/// A wrapper type for a usize with integrated range bound check.
+#[derive(Copy, Clone)]
+pub struct BoundedUsize<const MAX_INCLUSIVE: usize>(usize);
+
+static CURR_IRQ_MANAGER: ReadWriteExclusive<
+    &'static (dyn interface::IRQManager<IRQNumberType = IRQNumber> + Sync),
+> = ReadWriteExclusive::new(&null_irq_manager::NULL_IRQ_MANAGER);
+
+impl<T> IRQHandlerDescriptor<T>
+where
+    T: Copy,
+{
+    /// Create an instance.
+    pub const fn new(
+        number: T,
+        name: &'static str,
+        handler: &'static (dyn interface::IRQHandler + Sync),
+    ) -> Self {
+        Self { number, name, handler }
+    }
+
+    /// Return the number.
+    pub const fn number(&self) -> T {
+        self.number
+    }
+
+    /// Return the name.
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Return the handler.
+    pub const fn handler(&self) -> &'static (dyn interface::IRQHandler + Sync) {
+        self.handler
+    }
+}
+
+impl<'irq_context> IRQContext<'irq_context> {
+    /// Creates an IRQContext token.
+    ///
+    /// # Safety
+    ///
+    /// - This must only be called when the current core is in an interrupt context and will
+    ///   not live beyond the end of it. That is, creation is allowed in interrupt vector
+    ///   functions. For example, in the ARMv8-A case, in `extern "C" fn current_elx_irq()`.
+    /// - Note that the lifetime `'irq_context` of the returned instance is unconstrained. User
+    ///   code must not be able to influence the lifetime picked for this type, since that might
+    ///   cause it to be inferred to `'static`.
+    #[inline(always)]
+    pub unsafe fn new() -> Self {
+        IRQContext { _0: PhantomData }
+    }
+}
+
+impl<const MAX_INCLUSIVE: usize> BoundedUsize<{ MAX_INCLUSIVE }> {
+    /// Creates a new instance if number <= MAX_INCLUSIVE.
+    pub const fn new(number: usize) -> Self {
+        assert!(number <= MAX_INCLUSIVE);
+
+        Self(number)
+    }
+
+    /// Return the wrapped number.
+    pub const fn get(self) -> usize {
+        self.0
+    }
+}
+
+impl<const MAX_INCLUSIVE: usize> fmt::Display for BoundedUsize<{ MAX_INCLUSIVE }> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Executes the provided closure while IRQs are masked on the executing core.
+///
+/// While the function temporarily changes the HW state of the executing core, it restores it to
+/// the previous state before returning, so this is deemed safe.
+#[inline(always)]
+pub fn exec_with_irq_masked<T>(f: impl FnOnce() -> T) -> T {
+    let saved = local_irq_mask_save();
+    let ret = f();
+    local_irq_restore(saved);
+
+    ret
+}
+
+/// Register a new IRQ manager.
+pub fn register_irq_manager(
+    new_manager: &'static (dyn interface::IRQManager<IRQNumberType = IRQNumber> + Sync),
+) {
+    CURR_IRQ_MANAGER.write(|manager| *manager = new_manager);
+}
+
+/// Return a reference to the currently registered IRQ manager.
+///
+/// This is the IRQ manager used by the architectural interrupt handling code.
+pub fn irq_manager() -> &'static dyn interface::IRQManager<IRQNumberType = IRQNumber> {
+    CURR_IRQ_MANAGER.read(|manager| *manager)
+}
@@ -0,0 +1,482 @@
+//! Chainloader wire protocol.
+//!
+//! After the board has sent three `0x03` bytes, it keeps sending `xmodem::CRC_MODE` once a second
+//! until the host starts talking. A host that answers with `SOH` or `xmodem::STX` is served by the
+//! `xmodem` receiver, one that starts with `baud::MAGIC` changes the line speed first (see `baud`)
+//! and one that starts with `monitor::MAGIC` runs a single monitor command (see `monitor`).
+//! Otherwise the transfer runs as follows (all integers are little-endian):
+//!
+//! 1. Host sends the header: `MAGIC`, flags (u32, see `FLAG_LZ4`), number of payload bytes on the
+//!    wire (u32), size of the image once unpacked (u32), CRC-32 of the unpacked image (u32) and
+//!    the CRC-32 of the preceding 20 header bytes (u32). The board answers `ACK`, `NAK` to have
+//!    the header sent again, or `CAN` if the image does not fit.
+//!    With `FLAG_CMDLINE`, a single frame (see below) with index `CMDLINE_INDEX` follows, holding
+//!    the command line for the payload.
+//! 2. Host sends the payload in frames of at most `BLOCK_SIZE` bytes:
+//!    `SOH`, block index (u32), data length (u16), data, CRC-32 over index, length and data (u32).
+//!    The board answers `ACK` once the block is stored, or `NAK` to request a retransmission.
+//!    Bytes before `SOH` are skipped, so the host may pad with zeros to flush out a frame the
+//!    board is still waiting on.
+//!    A compressed payload is unpacked on the fly, straight to where the image is staged.
+//! 3. Once all blocks are in, the board checks the CRC-32 of the whole image in memory, moves it
+//!    to where it runs and sends a final `ACK`.
+//!
+//! With `FLAG_MANIFEST`, the payload is a list of images for the board to load and the transfer
+//! continues with those, see `manifest`.
+//!
+//! With `FLAG_INITRD`, the payload must be a Linux `Image`. Once the board has acknowledged it,
+//! the host transfers the initramfs the same way, starting over at step 1 with a header of its
+//! own.
+//!
+//! A loader built with the `secure-load` feature only runs payloads signed with its key, see
+//! `signature`. It takes no command line and no manifest, which would get around it.
+//!
+//! Whenever the board gives up it sends `CAN` twice, followed by a human readable reason on the
+//! console.
+//! If the line stays silent for `IDLE_TIMEOUT` in the middle of a transfer, the board gives up as
+//! well and requests the binary again.
+
+pub mod baud;
+mod crc32;
+pub mod elf;
+pub mod fdt;
+pub mod linux;
+mod lz4;
+pub mod manifest;
+pub mod monitor;
+#[cfg(feature = "secure-load")]
+mod signature;
+pub mod xmodem;
+
+use core::fmt;
+use core::ops::Range;
+use core::time::Duration;
+use crate::console::console;
+use crate::time;
+
+pub use crc32::{checksum, Crc32};
+
+/// Start of a header: "RPML".
+pub const MAGIC: [u8; 4] = *b"RPML";
+
+/// Header flag: the payload is an LZ4 block, see `lz4`.
+pub const FLAG_LZ4: u32 = 1 << 0;
+
+/// Header flag: a command line for the payload follows the header, see `receive_cmdline`.
+pub const FLAG_CMDLINE: u32 = 1 << 1;
+
+/// Header flag: an initramfs follows the payload, which is a Linux `Image`.
+pub const FLAG_INITRD: u32 = 1 << 2;
+
+/// Header flag: the payload is a manifest, see `manifest`.
+pub const FLAG_MANIFEST: u32 = 1 << 3;
+
+#[cfg(not(feature = "secure-load"))]
+const SUPPORTED_FLAGS: u32 = FLAG_LZ4 | FLAG_CMDLINE | FLAG_INITRD | FLAG_MANIFEST;
+
+/// The command line is not covered by the signature, and manifest regions are written in place
+/// before a signature could be checked.
+#[cfg(feature = "secure-load")]
+const SUPPORTED_FLAGS: u32 = FLAG_LZ4 | FLAG_INITRD;
+
+/// Maximum payload bytes per frame.
+pub const BLOCK_SIZE: usize = 1024;
+
+/// Frame index of the command line, which comes right before block 0.
+pub const CMDLINE_INDEX: u32 = u32::MAX;
+
+/// Longest command line the host may send. One byte of a frame is kept for the terminating NUL.
+pub const CMDLINE_MAX: usize = BLOCK_SIZE - 1;
+
+/// Attempts per header or block before the transfer is aborted.
+const MAX_RETRIES: usize = 10;
+
+/// How long the line may stay silent before a transfer is given up. 3 s unless the build sets
+/// `IDLE_TIMEOUT_MS`, see the Makefile.
+pub const IDLE_TIMEOUT: Duration = Duration::from_millis(match option_env!("IDLE_TIMEOUT_MS") {
+    Some(x) if !x.is_empty() => parse_millis(x),
+    _ => 3000,
+});
+
+/// `IDLE_TIMEOUT_MS` as a number. Anything else fails the build.
+const fn parse_millis(x: &str) -> u64 {
+    let digits = x.as_bytes();
+    let mut value: u64 = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        assert!(digits[i].is_ascii_digit(), "IDLE_TIMEOUT_MS must be a number of milliseconds");
+        value = value * 10 + (digits[i] - b'0') as u64;
+        i += 1;
+    }
+
+    assert!(value > 0, "IDLE_TIMEOUT_MS must not be zero");
+    value
+}
+
+pub const SOH: u8 = 0x01;
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+pub const CAN: u8 = 0x18;
+
+/// Transfer failures.
+pub enum Error {
+    /// Nobody answered the request for a binary.
+    Idle,
+    /// The host went silent in the middle of a transfer.
+    Timeout,
+    /// No intact header arrived.
+    Header,
+    /// The header asks for features this loader does not have.
+    Flags(u32),
+    /// The payload is bigger than the space available at the load address.
+    TooBig { size: u32, max: usize },
+    /// A block still failed its checksum after `MAX_RETRIES` attempts.
+    Block { index: u32 },
+    /// The compressed payload is corrupt.
+    Decompress(&'static str),
+    /// All blocks arrived, but the image in memory does not match the header checksum.
+    Checksum { expected: u32, actual: u32 },
+    /// The payload looks like an ELF file, but cannot be loaded.
+    Elf(&'static str),
+    /// An ELF segment lies outside the free memory, or would overwrite the loader or the
+    /// received image.
+    Segment { index: usize, start: usize, end: usize },
+    /// The payload is a Linux `Image`, but cannot be booted.
+    Linux(&'static str),
+    /// The device tree could not be prepared for Linux.
+    Fdt(&'static str),
+    /// The image does not end with a signature.
+    #[cfg(feature = "secure-load")]
+    Unsigned,
+    /// The signature does not match the image or was made with another key.
+    #[cfg(feature = "secure-load")]
+    Signature,
+    /// The manifest cannot be loaded.
+    Manifest(&'static str),
+    /// A manifest region lies outside the free memory or overlaps another.
+    Region { index: usize, start: usize, end: usize },
+    /// A monitor command was refused.
+    Monitor(&'static str),
+    /// The XMODEM sender cancelled the transfer.
+    Cancelled,
+    /// The XMODEM sender did not follow the protocol.
+    Xmodem(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Idle => write!(f, "no binary offered"),
+            Error::Timeout => write!(f, "nothing received for {:?}", IDLE_TIMEOUT),
+            Error::Header => write!(f, "no valid header after {} attempts", MAX_RETRIES),
+            Error::Flags(x) => write!(f, "unsupported header flags {:#x}", x),
+            Error::TooBig { size, max } => write!(
+                f,
+                "image too big: {} bytes, but only {} bytes fit at the load address",
+                size, max
+            ),
+            Error::Block { index } => {
+                write!(f, "block {} failed after {} attempts", index, MAX_RETRIES)
+            }
+            Error::Decompress(x) => write!(f, "bad compressed payload: {}", x),
+            Error::Checksum { expected, actual } => write!(
+                f,
+                "image checksum mismatch (expected {:#010x}, got {:#010x})",
+                expected, actual
+            ),
+            Error::Elf(x) => write!(f, "bad ELF file: {}", x),
+            Error::Segment { index, start, end } => write!(
+                f,
+                "ELF segment {} at {:#x}..{:#x} is outside the free memory or overlaps the loader or \
+                the received image",
+                index, start, end
+            ),
+            Error::Linux(x) => write!(f, "cannot boot Linux: {}", x),
+            Error::Fdt(x) => write!(f, "bad device tree: {}", x),
+            #[cfg(feature = "secure-load")]
+            Error::Unsigned => write!(f, "image is not signed"),
+            #[cfg(feature = "secure-load")]
+            Error::Signature => write!(f, "bad signature"),
+            Error::Manifest(x) => write!(f, "bad manifest: {}", x),
+            Error::Region { index, start, end } => write!(
+                f,
+                "manifest region {} at {:#x}..{:#x} is outside the free memory or overlaps another",
+                index, start, end
+            ),
+            Error::Monitor(x) => write!(f, "bad monitor command: {}", x),
+            Error::Cancelled => write!(f, "cancelled by the sender"),
+            Error::Xmodem(x) => write!(f, "XMODEM protocol error: {}", x),
+        }
+    }
+}
+
+/// Transfer header as sent by the host.
+pub struct Header {
+    pub flags: u32,
+    /// Payload bytes on the wire.
+    pub size: u32,
+    /// Image bytes once unpacked.
+    pub image_size: u32,
+    pub crc: u32,
+}
+
+fn send(byte: u8) {
+    console().write_char(byte as char);
+}
+
+fn read_byte() -> Result<u8, Error> {
+    match console().read_char_timeout(IDLE_TIMEOUT) {
+        Some(c) => Ok(c as u8),
+        None => Err(Error::Timeout),
+    }
+}
+
+fn read_bytes(buf: &mut [u8]) -> Result<(), Error> {
+    for b in buf.iter_mut() {
+        *b = read_byte()?;
+    }
+
+    Ok(())
+}
+
+fn read_u16() -> Result<u16, Error> {
+    let mut raw = [0u8; 2];
+    read_bytes(&mut raw)?;
+    Ok(u16::from_le_bytes(raw))
+}
+
+fn read_u32() -> Result<u32, Error> {
+    let mut raw = [0u8; 4];
+    read_bytes(&mut raw)?;
+    Ok(u32::from_le_bytes(raw))
+}
+
+/// Tell the host the transfer failed. Every error except `Error::Idle` ends this way.
+pub fn abort() {
+    // XMODEM senders want to see two, to tell it from line noise.
+    send(CAN);
+    send(CAN);
+}
+
+/// Tell the host the payload has been received and is ready to run.
+pub fn confirm() {
+    send(ACK);
+}
+
+/// How the host started talking.
+pub enum Start {
+    /// The first byte of `MAGIC`.
+    Minipush,
+    /// The first byte of `baud::MAGIC`.
+    Baud,
+    /// The first byte of `monitor::MAGIC`.
+    Monitor,
+    /// The header byte of the first XMODEM packet.
+    Xmodem(u8),
+}
+
+/// Wait up to `IDLE_TIMEOUT` for the host to start a transfer.
+///
+/// XMODEM senders only start once the receiver asks for it, so keep asking while waiting.
+pub fn receive_start() -> Result<Start, Error> {
+    let deadline = time::time_manager().uptime() + IDLE_TIMEOUT;
+    let mut next_request = time::time_manager().uptime();
+
+    loop {
+        let now = time::time_manager().uptime();
+        if now >= deadline {
+            return Err(Error::Idle);
+        }
+        if now >= next_request {
+            send(xmodem::CRC_MODE);
+            next_request = now + Duration::from_secs(1);
+        }
+
+        match console().read_char_timeout(Duration::from_millis(100)).map(|c| c as u8) {
+            Some(x) if x == MAGIC[0] => return Ok(Start::Minipush),
+            Some(x) if x == baud::MAGIC[0] => return Ok(Start::Baud),
+            // Poking memory and jumping around would get around the signature check.
+            Some(x) if x == monitor::MAGIC[0] && cfg!(not(feature = "secure-load")) => {
+                return Ok(Start::Monitor)
+            }
+            Some(x) if x == SOH || x == xmodem::STX => return Ok(Start::Xmodem(x)),
+            _ => {}
+        }
+    }
+}
+
+/// Receive and check the transfer header, whose first byte was consumed by `receive_start`.
+///
+/// The header is not acknowledged yet, see `accept_header`.
+pub fn receive_header() -> Result<Header, Error> {
+    let magic = u32::from_le_bytes(MAGIC);
+
+    for attempt in 0..MAX_RETRIES {
+        // Skip anything in front of the magic.
+        let mut window: u32 = if attempt == 0 { u32::from(MAGIC[0]) << 24 } else { 0 };
+        while window != magic {
+            window = (window >> 8) | (u32::from(read_byte()?) << 24);
+        }
+
+        let mut raw = [0u8; 20];
+        raw[..4].copy_from_slice(&MAGIC);
+        read_bytes(&mut raw[4..])?;
+        let header_crc = read_u32()?;
+
+        if checksum(&raw) != header_crc {
+            send(NAK);
+            continue;
+        }
+
+        let field = |i: usize| u32::from_le_bytes([raw[i], raw[i + 1], raw[i + 2], raw[i + 3]]);
+        return Ok(Header {
+            flags: field(4),
+            size: field(8),
+            image_size: field(12),
+            crc: field(16),
+        });
+    }
+
+    Err(Error::Header)
+}
+
+/// Acknowledge `header` if its image fits into `max` bytes.
+pub fn accept_header(header: &Header, max: usize) -> Result<(), Error> {
+    if header.flags & !SUPPORTED_FLAGS != 0 {
+        return Err(Error::Flags(header.flags & !SUPPORTED_FLAGS));
+    }
+    if header.image_size as usize > max {
+        return Err(Error::TooBig { size: header.image_size, max });
+    }
+    if header.flags & FLAG_LZ4 == 0 && header.size != header.image_size {
+        return Err(Error::Header);
+    }
+
+    send(ACK);
+    Ok(())
+}
+
+/// Receive block `index` into `buf`, which is as long as the block may be. Shorter blocks are
+/// accepted down to `min_len` bytes.
+fn receive_block(index: u32, buf: &mut [u8], min_len: usize) -> Result<usize, Error> {
+    let mut frame = [0u8; BLOCK_SIZE];
+
+    for _ in 0..MAX_RETRIES {
+        while read_byte()? != SOH {}
+
+        let seq = read_u32()?;
+        let len = read_u16()?;
+        if usize::from(len) > BLOCK_SIZE {
+            send(NAK);
+            continue;
+        }
+
+        let data = &mut frame[..usize::from(len)];
+        read_bytes(data)?;
+        let frame_crc = read_u32()?;
+
+        let mut crc = Crc32::new();
+        crc.update(&seq.to_le_bytes());
+        crc.update(&len.to_le_bytes());
+        crc.update(data);
+        if crc.finish() != frame_crc {
+            send(NAK);
+            continue;
+        }
+
+        if seq == index && (min_len..=buf.len()).contains(&data.len()) {
+            buf[..data.len()].copy_from_slice(data);
+            return Ok(data.len());
+        }
+
+        // Our previous ACK got lost and the host repeated the last block. Acknowledge it again.
+        if seq.wrapping_add(1) == index {
+            send(ACK);
+            continue;
+        }
+
+        send(NAK);
+    }
+
+    Err(Error::Block { index })
+}
+
+/// Receive the command line announced by `FLAG_CMDLINE` into `buf` and terminate it with a NUL.
+/// Returns its length without the NUL.
+pub fn receive_cmdline(buf: &mut [u8; BLOCK_SIZE]) -> Result<usize, Error> {
+    let len = receive_block(CMDLINE_INDEX, &mut buf[..CMDLINE_MAX], 0)?;
+    buf[len] = 0;
+    send(ACK);
+
+    Ok(len)
+}
+
+/// Where to receive a payload of `size` bytes: the top of `window`, 16-byte aligned, so that the
+/// ELF segments, which usually start at the bottom, can be copied out without overlapping it.
+pub fn staging_addr(window: &Range<usize>, size: u32) -> Result<*mut u8, Error> {
+    // Rounding down must not leave the window, so the payload has to fit above the first aligned
+    // address in it.
+    let max = window.end.saturating_sub((window.start + 0xF) & !0xF);
+    if size as usize > max {
+        return Err(Error::TooBig { size, max });
+    }
+
+    Ok(((window.end - size as usize) & !0xF) as *mut u8)
+}
+
+/// Receive the payload announced by `header`, unpack it to `addr` if needed and check the image
+/// against the header checksum.
+///
+/// The transfer is still open afterwards, see `confirm`.
+///
+/// # Safety
+///
+/// - `addr` must be valid for writes of `header.image_size` bytes.
+pub unsafe fn receive_payload(header: &Header, addr: *mut u8) -> Result<&'static [u8], Error> {
+    let size = header.size as usize;
+    let image_size = header.image_size as usize;
+    let compressed = header.flags & FLAG_LZ4 != 0;
+    let mut decoder = lz4::Decoder::new(addr, image_size);
+    let mut block = [0u8; BLOCK_SIZE];
+
+    let mut offset = 0;
+    let mut index = 0;
+    while offset < size {
+        let len = core::cmp::min(BLOCK_SIZE, size - offset);
+
+        receive_block(index, &mut block[..len], len)?;
+        if compressed {
+            decoder.feed(&block[..len])?;
+        } else {
+            core::ptr::copy_nonoverlapping(block.as_ptr(), addr.add(offset), len);
+        }
+        send(ACK);
+
+        offset += len;
+        index += 1;
+    }
+
+    if compressed && decoder.finish()? != image_size {
+        return Err(Error::Decompress("image shorter than announced"));
+    }
+
+    let image = core::slice::from_raw_parts(addr, image_size);
+    let actual = checksum(image);
+    if actual != header.crc {
+        return Err(Error::Checksum { expected: header.crc, actual });
+    }
+
+    Ok(image)
+}
+
+/// Check that a received image may run and return it as it is to be loaded.
+///
+/// With the `secure-load` feature, the image must be signed and loses its signature here. Without
+/// it, every image is taken as is.
+pub fn authenticate(image: &'static [u8]) -> Result<&'static [u8], Error> {
+    #[cfg(feature = "secure-load")]
+    return signature::verify(image);
+
+    #[cfg(not(feature = "secure-load"))]
+    Ok(image)
+}
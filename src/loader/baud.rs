@@ -0,0 +1,139 @@
+//! Baud rate negotiation, run before a transfer when the host asks for a different line speed or
+//! frame format.
+//!
+//! 1. Host sends `MAGIC`, the new baud rate (u32), the frame format (u8, see `FRAMING_PARITY` and
+//!    the other `FRAMING_*` bits) and the CRC-32 of all three (u32). The board answers `NAK` if
+//!    the request is damaged or asks for an unknown format, otherwise `ACK` and switches right
+//!    after it.
+//! 2. Host switches as well and sends `PROBE`. The board echoes it back at the new rate.
+//! 3. Host answers the echo with `ACK`.
+//!
+//! If any step fails or times out, both sides fall back to the rate they had before. Either way,
+//! the board then requests the binary again, at whatever rate was agreed on.
+
+use core::time::Duration;
+use crate::bsp::console::{DataBits, LineConfig, Parity, StopBits};
+use crate::console::console;
+use super::{checksum, read_bytes, read_u32, send, ACK, NAK};
+
+/// Start of a baud rate request: "BAUD".
+pub const MAGIC: [u8; 4] = *b"BAUD";
+
+/// Frame format bits 0..=1: no parity (0), even (1) or odd (2).
+pub const FRAMING_PARITY: u8 = 0b11;
+
+/// Frame format bit: two stop bits instead of one.
+pub const FRAMING_TWO_STOP_BITS: u8 = 1 << 2;
+
+/// Frame format bit: RTS/CTS flow control.
+pub const FRAMING_RTS_CTS: u8 = 1 << 3;
+
+/// What the line runs at.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Setting {
+    pub baud: u32,
+    pub line: LineConfig,
+}
+
+/// Bit patterns the host sends at the new rate, to be echoed back.
+pub const PROBE: [u8; 16] = [
+    0x55, 0xAA, 0x33, 0xCC, 0x0F, 0xF0, 0x5A, 0xA5, 0x00, 0xFF, 0x69, 0x96, 0x3C, 0xC3, 0x99, 0x66,
+];
+
+/// How long to wait for each step at the new rate. The host has to reconfigure its port first.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn read_byte(timeout: Duration) -> Option<u8> {
+    console().read_char_timeout(timeout).map(|c| c as u8)
+}
+
+/// The line configuration a frame format byte stands for, if it is a valid one.
+fn line_config(framing: u8) -> Option<LineConfig> {
+    let parity = match framing & FRAMING_PARITY {
+        0 => Parity::None,
+        1 => Parity::Even,
+        2 => Parity::Odd,
+        _ => return None,
+    };
+    if framing & !(FRAMING_PARITY | FRAMING_TWO_STOP_BITS | FRAMING_RTS_CTS) != 0 {
+        return None;
+    }
+
+    let stop_bits = match framing & FRAMING_TWO_STOP_BITS {
+        0 => StopBits::One,
+        _ => StopBits::Two,
+    };
+
+    // The protocol is binary, so characters stay 8 bits wide.
+    Some(LineConfig {
+        data_bits: DataBits::Eight,
+        parity,
+        stop_bits,
+        flow_control: framing & FRAMING_RTS_CTS != 0,
+    })
+}
+
+/// Check for the probe at the new rate, skipping noise from the switch, and echo it. Succeeds once
+/// the host acknowledges the echo.
+fn echo_probe() -> Option<()> {
+    while read_byte(PROBE_TIMEOUT)? != PROBE[0] {}
+
+    let mut probe = PROBE;
+    for b in probe[1..].iter_mut() {
+        *b = read_byte(PROBE_TIMEOUT)?;
+    }
+    if probe != PROBE {
+        return None;
+    }
+
+    for &b in PROBE.iter() {
+        send(b);
+    }
+
+    read_byte(PROBE_TIMEOUT).filter(|&x| x == ACK).map(|_| ())
+}
+
+/// Serve a baud rate request, whose first byte was consumed by `receive_start`, and return the
+/// setting the line runs at afterwards.
+///
+/// `current` is the setting in use, `switch` reconfigures the UART.
+pub fn negotiate(current: Setting, switch: fn(Setting) -> Result<(), &'static str>) -> Setting {
+    let mut raw = [0u8; 9];
+    raw[0] = MAGIC[0];
+    if read_bytes(&mut raw[1..]).is_err() {
+        return current;
+    }
+    let crc = match read_u32() {
+        Ok(x) => x,
+        Err(_) => return current,
+    };
+
+    if raw[..4] != MAGIC || checksum(&raw) != crc {
+        send(NAK);
+        return current;
+    }
+
+    let line = match line_config(raw[8]) {
+        Some(x) => x,
+        None => {
+            send(NAK);
+            return current;
+        }
+    };
+    let requested = Setting { baud: u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]), line };
+    send(ACK);
+
+    // A setting the UART cannot do leaves the line as it was, and the host's probe goes
+    // unanswered.
+    if switch(requested).is_err() {
+        let _ = switch(current);
+        return current;
+    }
+    if echo_probe().is_some() {
+        return requested;
+    }
+
+    // The setting in use worked before, so switching back cannot fail.
+    let _ = switch(current);
+    current
+}
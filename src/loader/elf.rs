@@ -0,0 +1,310 @@
+//! Minimal ELF64 loader for statically linked AArch64 executables.
+
+use core::ops::Range;
+use super::Error;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_AARCH64: u16 = 183;
+const PT_LOAD: u32 = 1;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+/// A `PT_LOAD` program header.
+struct Segment {
+    offset: usize,
+    paddr: usize,
+    filesz: usize,
+    memsz: usize,
+}
+
+impl Segment {
+    fn range(&self) -> Range<usize> {
+        self.paddr..self.paddr + self.memsz
+    }
+}
+
+fn field<const N: usize>(image: &[u8], offset: usize) -> Result<[u8; N], Error> {
+    image
+        .get(offset..offset.saturating_add(N))
+        .and_then(|x| x.try_into().ok())
+        .ok_or(Error::Elf("truncated header"))
+}
+
+fn u16_at(image: &[u8], offset: usize) -> Result<u16, Error> {
+    field(image, offset).map(u16::from_le_bytes)
+}
+
+fn u32_at(image: &[u8], offset: usize) -> Result<u32, Error> {
+    field(image, offset).map(u32::from_le_bytes)
+}
+
+fn usize_at(image: &[u8], offset: usize) -> Result<usize, Error> {
+    field(image, offset).map(|x| u64::from_le_bytes(x) as usize)
+}
+
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// Whether `inner` lies within `outer`.
+fn contains(outer: &Range<usize>, inner: &Range<usize>) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
+
+pub fn is_elf(image: &[u8]) -> bool {
+    image.starts_with(&ELF_MAGIC)
+}
+
+/// Iterate over the `PT_LOAD` segments of a checked ELF image.
+fn segments(image: &[u8]) -> Result<impl Iterator<Item = Result<Segment, Error>> + '_, Error> {
+    let phoff = usize_at(image, 0x20)?;
+    let phentsize = usize::from(u16_at(image, 0x36)?);
+    let phnum = usize::from(u16_at(image, 0x38)?);
+
+    if phentsize < PHDR_SIZE {
+        return Err(Error::Elf("program header entries too small"));
+    }
+
+    Ok((0..phnum)
+        .map(move |i| -> Result<Option<Segment>, Error> {
+            let phdr = image
+                .get(phoff.saturating_add(i * phentsize)..)
+                .ok_or(Error::Elf("truncated program header"))?;
+            if u32_at(phdr, 0)? != PT_LOAD {
+                return Ok(None);
+            }
+
+            Ok(Some(Segment {
+                offset: usize_at(phdr, 0x08)?,
+                paddr: usize_at(phdr, 0x18)?,
+                filesz: usize_at(phdr, 0x20)?,
+                memsz: usize_at(phdr, 0x28)?,
+            }))
+        })
+        .filter_map(Result::transpose))
+}
+
+fn check_header(image: &[u8]) -> Result<(), Error> {
+    if image.len() < EHDR_SIZE {
+        return Err(Error::Elf("truncated header"));
+    }
+    if image[4] != ELFCLASS64 || image[5] != ELFDATA2LSB {
+        return Err(Error::Elf("not a little-endian ELF64 file"));
+    }
+    if u16_at(image, 0x10)? != ET_EXEC {
+        return Err(Error::Elf("not an executable"));
+    }
+    if u16_at(image, 0x12)? != EM_AARCH64 {
+        return Err(Error::Elf("not an AArch64 binary"));
+    }
+
+    Ok(())
+}
+
+/// Copy the `PT_LOAD` segments of `image` to their physical addresses, zero their BSS tails and
+/// return the entry point.
+///
+/// Nothing is written unless every segment is well-formed, lies within one of `windows` and stays
+/// clear of `reserved`.
+///
+/// # Safety
+///
+/// - `windows` must be unused RAM, apart from `reserved`.
+pub unsafe fn load(
+    image: &[u8],
+    windows: &[Range<usize>],
+    reserved: &[Range<usize>],
+) -> Result<usize, Error> {
+    check_header(image)?;
+    let entry = usize_at(image, 0x18)?;
+
+    let mut entry_loaded = false;
+    for (index, segment) in segments(image)?.enumerate() {
+        let segment = segment?;
+
+        if segment.filesz > segment.memsz
+            || segment.offset.checked_add(segment.filesz).map_or(true, |end| end > image.len())
+            || segment.paddr.checked_add(segment.memsz).is_none()
+        {
+            return Err(Error::Elf("malformed program header"));
+        }
+
+        let range = segment.range();
+        if !windows.iter().any(|w| contains(w, &range))
+            || reserved.iter().any(|r| overlaps(&range, r))
+        {
+            return Err(Error::Segment { index, start: range.start, end: range.end });
+        }
+
+        entry_loaded |= range.contains(&entry);
+    }
+
+    if !entry_loaded {
+        return Err(Error::Elf("entry point outside of the loaded segments"));
+    }
+
+    for segment in segments(image)? {
+        let segment = segment?;
+        let dst = segment.paddr as *mut u8;
+
+        core::ptr::copy_nonoverlapping(image.as_ptr().add(segment.offset), dst, segment.filesz);
+        core::ptr::write_bytes(dst.add(segment.filesz), 0, segment.memsz - segment.filesz);
+    }
+
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Offset of the segment data in `build`'s images.
+    const DATA: usize = 0x100;
+
+    /// An executable with a program header per `(offset, paddr, filesz, memsz)` and the bytes
+    /// 1, 2, 3, ... from `DATA` on.
+    fn build(entry: usize, segments: &[(usize, usize, usize, usize)]) -> [u8; 0x140] {
+        let mut image = [0u8; 0x140];
+        image[..4].copy_from_slice(&ELF_MAGIC);
+        image[4] = ELFCLASS64;
+        image[5] = ELFDATA2LSB;
+        image[0x10..0x12].copy_from_slice(&ET_EXEC.to_le_bytes());
+        image[0x12..0x14].copy_from_slice(&EM_AARCH64.to_le_bytes());
+        image[0x18..0x20].copy_from_slice(&(entry as u64).to_le_bytes());
+        image[0x20..0x28].copy_from_slice(&(EHDR_SIZE as u64).to_le_bytes());
+        image[0x36..0x38].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        image[0x38..0x3A].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+
+        for (i, &(offset, paddr, filesz, memsz)) in segments.iter().enumerate() {
+            let phdr = &mut image[EHDR_SIZE + i * PHDR_SIZE..][..PHDR_SIZE];
+            phdr[..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+            for (at, value) in [(0x08, offset), (0x18, paddr), (0x20, filesz), (0x28, memsz)] {
+                phdr[at..at + 8].copy_from_slice(&(value as u64).to_le_bytes());
+            }
+        }
+
+        for (i, byte) in image[DATA..].iter_mut().enumerate() {
+            *byte = i as u8 + 1;
+        }
+        image
+    }
+
+    /// The memory of `buffer`, as a window to load to.
+    fn window(buffer: &[u8]) -> Range<usize> {
+        buffer.as_ptr() as usize..buffer.as_ptr() as usize + buffer.len()
+    }
+
+    fn elf_error(result: Result<usize, Error>) -> &'static str {
+        match result {
+            Err(Error::Elf(x)) => x,
+            _ => panic!("expected an ELF error"),
+        }
+    }
+
+    #[test]
+    fn loads_segments_and_zeroes_their_bss() {
+        let mut dst = [0xAAu8; 32];
+        let addr = dst.as_mut_ptr() as usize;
+        let image = build(addr + 4, &[(DATA, addr, 8, 16)]);
+
+        let entry = unsafe { load(&image, &[window(&dst)], &[]) };
+        assert!(matches!(entry, Ok(x) if x == addr + 4));
+        assert_eq!(dst[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(dst[8..16], [0; 8]);
+        assert_eq!(dst[16..], [0xAA; 16]);
+    }
+
+    #[test]
+    fn refuses_malformed_program_headers() {
+        let mut dst = [0xAAu8; 16];
+        let addr = dst.as_mut_ptr() as usize;
+        let windows = [window(&dst)];
+
+        // More file than memory.
+        let image = build(addr, &[(DATA, addr, 16, 8)]);
+        assert_eq!(elf_error(unsafe { load(&image, &windows, &[]) }), "malformed program header");
+
+        // File data beyond the end of the image.
+        let image = build(addr, &[(DATA, addr, 0x41, 0x41)]);
+        assert_eq!(elf_error(unsafe { load(&image, &windows, &[]) }), "malformed program header");
+        let image = build(addr, &[(usize::MAX, addr, 1, 1)]);
+        assert_eq!(elf_error(unsafe { load(&image, &windows, &[]) }), "malformed program header");
+
+        // Memory wrapping around the address space.
+        let image = build(addr, &[(DATA, usize::MAX - 4, 0, 8)]);
+        assert_eq!(elf_error(unsafe { load(&image, &windows, &[]) }), "malformed program header");
+
+        assert_eq!(dst, [0xAA; 16]);
+    }
+
+    #[test]
+    fn refuses_truncated_headers() {
+        let image = build(0, &[]);
+        let result = unsafe { load(&image[..EHDR_SIZE - 1], &[], &[]) };
+        assert_eq!(elf_error(result), "truncated header");
+
+        // Three program headers announced, the image ends after two.
+        let mut dst = [0xAAu8; 8];
+        let addr = dst.as_mut_ptr() as usize;
+        let image = build(addr, &[(0, addr, 8, 8); 3]);
+        let image = &image[..EHDR_SIZE + 2 * PHDR_SIZE];
+        assert_eq!(elf_error(unsafe { load(image, &[window(&dst)], &[]) }), "truncated header");
+        assert_eq!(dst, [0xAA; 8]);
+    }
+
+    #[test]
+    fn refuses_other_machines() {
+        let mut image = build(0, &[]);
+        image[0x12] = 62;
+        assert_eq!(elf_error(unsafe { load(&image, &[], &[]) }), "not an AArch64 binary");
+    }
+
+    #[test]
+    fn keeps_segments_out_of_reserved_memory() {
+        let mut dst = [0xAAu8; 32];
+        let addr = dst.as_mut_ptr() as usize;
+        let image = build(addr, &[(DATA, addr, 8, 8), (DATA, addr + 16, 8, 16)]);
+
+        // Only touching the end of the second segment.
+        let reserved = [0..8, addr + 31..addr + 40];
+        let result = unsafe { load(&image, &[window(&dst)], &reserved) };
+        assert!(matches!(result, Err(Error::Segment { index: 1, .. })));
+
+        // Right behind it is fine.
+        let reserved = [0..8, addr + 32..addr + 40];
+        assert!(unsafe { load(&image, &[window(&dst)], &reserved) }.is_ok());
+    }
+
+    #[test]
+    fn keeps_segments_inside_the_windows() {
+        let mut dst = [0xAAu8; 32];
+        let addr = dst.as_mut_ptr() as usize;
+        let image = build(addr, &[(DATA, addr, 8, 8), (DATA, addr + 16, 8, 16)]);
+
+        // The second segment sticks out of the first window by a byte, and does not reach into
+        // the second one.
+        let windows = [addr..addr + 31, addr + 40..addr + 48];
+        let result = unsafe { load(&image, &windows, &[]) };
+        assert!(matches!(result, Err(Error::Segment { index: 1, .. })));
+        assert_eq!(dst, [0xAA; 32]);
+
+        // Each segment in a window of its own.
+        let windows = [addr..addr + 8, addr + 16..addr + 32];
+        assert!(unsafe { load(&image, &windows, &[]) }.is_ok());
+    }
+
+    #[test]
+    fn needs_the_entry_point_in_a_segment() {
+        let mut dst = [0xAAu8; 16];
+        let addr = dst.as_mut_ptr() as usize;
+        let image = build(addr + 8, &[(DATA, addr, 8, 8)]);
+
+        let result = unsafe { load(&image, &[window(&dst)], &[]) };
+        assert_eq!(elf_error(result), "entry point outside of the loaded segments");
+        assert_eq!(dst, [0xAA; 16]);
+    }
+}
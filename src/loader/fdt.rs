@@ -0,0 +1,625 @@
+//! Flattened device tree, as handed over by the firmware.
+//!
+//! Just enough to set properties of `/chosen` for Linux. The blob is copied to a buffer with room
+//! to grow and edited there, the firmware's copy is left alone.
+
+use core::ops::Range;
+use super::Error;
+
+const FDT_MAGIC: u32 = 0xD00D_FEED;
+
+/// Size of the fixed part of the header.
+const HEADER_SIZE: usize = 40;
+
+/// First version with `size_dt_struct` in the header.
+const MIN_VERSION: u32 = 17;
+
+// Header fields, as byte offsets.
+const TOTAL_SIZE: usize = 0x04;
+const OFF_DT_STRUCT: usize = 0x08;
+const OFF_DT_STRINGS: usize = 0x0C;
+const VERSION: usize = 0x14;
+const SIZE_DT_STRINGS: usize = 0x20;
+const SIZE_DT_STRUCT: usize = 0x24;
+
+// Structure block tokens.
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// The memory occupied by the device tree blob at `addr`, if there is one.
+///
+/// # Safety
+///
+/// - A non-zero `addr` must be readable for the blob header.
+pub unsafe fn blob_range(addr: usize) -> Option<Range<usize>> {
+    if addr == 0 || addr % 8 != 0 {
+        return None;
+    }
+
+    // Header fields are big-endian.
+    let header = addr as *const u32;
+    if u32::from_be(header.read()) != FDT_MAGIC {
+        return None;
+    }
+    let total_size = u32::from_be(header.add(1).read()) as usize;
+    if total_size < HEADER_SIZE {
+        return None;
+    }
+
+    Some(addr..addr.checked_add(total_size)?)
+}
+
+fn align4(x: usize) -> usize {
+    (x + 3) & !3
+}
+
+/// A device tree blob, read only.
+struct Blob<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Blob<'a> {
+    fn u32_at(&self, offset: usize) -> Result<u32, Error> {
+        self.buf
+            .get(offset..offset + 4)
+            .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+            .ok_or(Error::Fdt("truncated blob"))
+    }
+
+    fn header(&self, field: usize) -> usize {
+        // Only called once `check` passed.
+        self.u32_at(field).unwrap_or(0) as usize
+    }
+
+    /// Only the usual layout is supported: the strings block comes last, after the structure
+    /// block. Free space may follow it, as left by tools that pad the blob.
+    fn check(&self) -> Result<(), Error> {
+        if self.u32_at(VERSION)? < MIN_VERSION {
+            return Err(Error::Fdt("blob version too old"));
+        }
+
+        let total_size = self.header(TOTAL_SIZE);
+        let struct_end = self.header(OFF_DT_STRUCT) + self.header(SIZE_DT_STRUCT);
+        let strings_end = self.header(OFF_DT_STRINGS) + self.header(SIZE_DT_STRINGS);
+        if struct_end > self.header(OFF_DT_STRINGS)
+            || strings_end > total_size
+            || total_size > self.buf.len()
+        {
+            return Err(Error::Fdt("unsupported blob layout"));
+        }
+
+        Ok(())
+    }
+
+    /// The token at `offset` in the structure block and the offset of the next one.
+    fn token(&self, offset: usize) -> Result<(u32, usize), Error> {
+        let token = self.u32_at(offset)?;
+        let next = match token {
+            FDT_BEGIN_NODE => {
+                let name = self.buf.get(offset + 4..).ok_or(Error::Fdt("truncated blob"))?;
+                let len = name.iter().position(|&x| x == 0).ok_or(Error::Fdt("truncated blob"))?;
+                align4(offset + 4 + len + 1)
+            }
+            FDT_PROP => align4(offset + 12 + self.u32_at(offset + 4)? as usize),
+            FDT_END_NODE | FDT_NOP | FDT_END => offset + 4,
+            _ => return Err(Error::Fdt("bad structure block")),
+        };
+
+        if next > self.header(OFF_DT_STRINGS) {
+            return Err(Error::Fdt("bad structure block"));
+        }
+
+        Ok((token, next))
+    }
+
+    fn node_name(&self, offset: usize) -> &'a [u8] {
+        let name = &self.buf[offset + 4..];
+        &name[..name.iter().position(|&x| x == 0).unwrap_or(0)]
+    }
+
+    /// Name and value of the property at `offset`.
+    fn property(&self, offset: usize) -> Result<(&'a [u8], &'a [u8]), Error> {
+        let len = self.u32_at(offset + 4)? as usize;
+        let value = self.buf.get(offset + 12..offset + 12 + len);
+        let value = value.ok_or(Error::Fdt("truncated blob"))?;
+
+        let start = self.header(OFF_DT_STRINGS) + self.u32_at(offset + 8)? as usize;
+        let name = self.buf.get(start..).ok_or(Error::Fdt("bad property name"))?;
+        let len = name.iter().position(|&x| x == 0).ok_or(Error::Fdt("bad property name"))?;
+
+        Ok((&name[..len], value))
+    }
+
+    /// The RAM banks in `reg` of `/memory`, along with the root's `#address-cells` and
+    /// `#size-cells`.
+    fn memory_reg(&self) -> Result<(&'a [u8], usize, usize), Error> {
+        // The defaults of the specification.
+        let (mut address_cells, mut size_cells) = (2, 1);
+        let mut offset = self.header(OFF_DT_STRUCT);
+        let mut depth = 0;
+        let mut in_memory = false;
+
+        loop {
+            let (token, next) = self.token(offset)?;
+            match token {
+                FDT_BEGIN_NODE => {
+                    depth += 1;
+                    let name = self.node_name(offset);
+                    in_memory = depth == 2 && (name == b"memory" || name.starts_with(b"memory@"));
+                }
+                FDT_PROP if depth == 1 => match self.property(offset)? {
+                    (b"#address-cells", x) if x.len() == 4 => {
+                        address_cells = self.u32_at(offset + 12)? as usize
+                    }
+                    (b"#size-cells", x) if x.len() == 4 => {
+                        size_cells = self.u32_at(offset + 12)? as usize
+                    }
+                    _ => {}
+                },
+                FDT_PROP if in_memory => {
+                    if let (b"reg", reg) = self.property(offset)? {
+                        return Ok((reg, address_cells, size_cells));
+                    }
+                }
+                FDT_END_NODE => {
+                    depth -= 1;
+                    in_memory = false;
+                }
+                FDT_END => return Err(Error::Fdt("no /memory node")),
+                _ => {}
+            }
+            offset = next;
+        }
+    }
+}
+
+/// The big-endian number in `bytes`, at most two cells.
+fn cells(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, &x| acc << 8 | u64::from(x))
+}
+
+/// End of the RAM bank that contains `addr`, as `/memory` of the blob at `src` lists it. The
+/// firmware only lists what it leaves to the ARM cores.
+///
+/// # Safety
+///
+/// - `src` must be a blob as found by `blob_range`.
+pub unsafe fn memory_end(src: &Range<usize>, addr: usize) -> Result<usize, Error> {
+    let blob = Blob { buf: core::slice::from_raw_parts(src.start as *const u8, src.len()) };
+    blob.check()?;
+
+    let (reg, address_cells, size_cells) = blob.memory_reg()?;
+    if address_cells > 2 || size_cells > 2 {
+        return Err(Error::Fdt("unsupported /memory cells"));
+    }
+
+    let (address_len, size_len) = (address_cells * 4, size_cells * 4);
+    for bank in reg.chunks_exact(address_len + size_len) {
+        let start = cells(&bank[..address_len]);
+        let end = start.saturating_add(cells(&bank[address_len..]));
+        if (start..end).contains(&(addr as u64)) {
+            return Ok(usize::try_from(end).unwrap_or(usize::MAX));
+        }
+    }
+
+    Err(Error::Fdt("the loader is in no RAM bank of /memory"))
+}
+
+/// A device tree blob in a buffer that it may grow into.
+pub struct Fdt {
+    buf: &'static mut [u8],
+}
+
+impl Fdt {
+    /// Copy the blob at `src` to `dst`, which has room for `capacity` bytes.
+    ///
+    /// # Safety
+    ///
+    /// - `src` must be a blob as found by `blob_range`.
+    /// - `dst` must be valid for writes of `capacity` bytes, not overlap `src` and stay unused for
+    ///   as long as the blob is needed.
+    pub unsafe fn copy(src: &Range<usize>, dst: *mut u8, capacity: usize) -> Result<Self, Error> {
+        if src.len() > capacity {
+            return Err(Error::Fdt("no room for the device tree"));
+        }
+        core::ptr::copy_nonoverlapping(src.start as *const u8, dst, src.len());
+
+        let fdt = Self { buf: core::slice::from_raw_parts_mut(dst, capacity) };
+        fdt.blob().check()?;
+
+        Ok(fdt)
+    }
+
+    fn blob(&self) -> Blob<'_> {
+        Blob { buf: self.buf }
+    }
+
+    fn set_u32(&mut self, offset: usize, value: u32) {
+        self.buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    fn header(&self, field: usize) -> usize {
+        self.blob().header(field)
+    }
+
+    fn set_header(&mut self, field: usize, value: usize) {
+        self.set_u32(field, value as u32);
+    }
+
+    /// The memory the blob occupies now.
+    pub fn range(&self) -> Range<usize> {
+        let start = self.buf.as_ptr() as usize;
+        start..start + self.header(TOTAL_SIZE)
+    }
+
+    /// Replace `remove` bytes of the structure block at `at` with the concatenation of `insert`.
+    fn splice_struct(&mut self, at: usize, remove: usize, insert: &[&[u8]]) -> Result<(), Error> {
+        let len: usize = insert.iter().map(|x| x.len()).sum();
+        let total_size = self.header(TOTAL_SIZE);
+        let new_size = total_size - remove + len;
+        if new_size > self.buf.len() {
+            return Err(Error::Fdt("no room to grow the device tree"));
+        }
+
+        self.buf.copy_within(at + remove..total_size, at + len);
+        let mut offset = at;
+        for part in insert {
+            self.buf[offset..offset + part.len()].copy_from_slice(part);
+            offset += part.len();
+        }
+
+        let grow = |x: usize| x - remove + len;
+        self.set_header(TOTAL_SIZE, new_size);
+        self.set_header(OFF_DT_STRINGS, grow(self.header(OFF_DT_STRINGS)));
+        self.set_header(SIZE_DT_STRUCT, grow(self.header(SIZE_DT_STRUCT)));
+
+        Ok(())
+    }
+
+    /// Offset of `name` in the strings block, which it is appended to if missing.
+    fn string(&mut self, name: &str) -> Result<usize, Error> {
+        let start = self.header(OFF_DT_STRINGS);
+        let size = self.header(SIZE_DT_STRINGS);
+        let strings = &self.buf[start..start + size];
+
+        // Any match will do, even the tail of a longer string.
+        let len = name.len() + 1;
+        if let Some(x) = strings
+            .windows(len)
+            .position(|x| &x[..name.len()] == name.as_bytes() && x[name.len()] == 0)
+        {
+            return Ok(x);
+        }
+
+        // Free space after the strings block is used up first.
+        let end = start + size;
+        if end + len > self.buf.len() {
+            return Err(Error::Fdt("no room to grow the device tree"));
+        }
+        self.buf[end..end + name.len()].copy_from_slice(name.as_bytes());
+        self.buf[end + name.len()] = 0;
+        self.set_header(SIZE_DT_STRINGS, size + len);
+        self.set_header(TOTAL_SIZE, usize::max(self.header(TOTAL_SIZE), end + len));
+
+        Ok(size)
+    }
+
+    /// Offset right behind the name of `/chosen`, where its properties start. The node is added
+    /// if the blob has none.
+    fn chosen(&mut self) -> Result<usize, Error> {
+        let mut offset = self.header(OFF_DT_STRUCT);
+        let mut depth = 0;
+
+        loop {
+            let (token, next) = self.blob().token(offset)?;
+            match token {
+                FDT_BEGIN_NODE => {
+                    depth += 1;
+                    if depth == 2 && self.blob().node_name(offset) == b"chosen" {
+                        return Ok(next);
+                    }
+                }
+                FDT_END_NODE if depth == 1 => break,
+                FDT_END_NODE => depth -= 1,
+                FDT_END => return Err(Error::Fdt("bad structure block")),
+                _ => {}
+            }
+            offset = next;
+        }
+
+        // `offset` is at the end of the root node.
+        let mut node = [0u8; 16];
+        node[..4].copy_from_slice(&FDT_BEGIN_NODE.to_be_bytes());
+        node[4..10].copy_from_slice(b"chosen");
+        node[12..16].copy_from_slice(&FDT_END_NODE.to_be_bytes());
+        self.splice_struct(offset, 0, &[&node])?;
+
+        Ok(offset + 12)
+    }
+
+    /// Set property `name` of `/chosen` to `value`.
+    pub fn set_chosen(&mut self, name: &str, value: &[u8]) -> Result<(), Error> {
+        let name_offset = self.string(name)?;
+        let properties = self.chosen()?;
+
+        let mut prop = [0u8; 12];
+        prop[..4].copy_from_slice(&FDT_PROP.to_be_bytes());
+        prop[4..8].copy_from_slice(&(value.len() as u32).to_be_bytes());
+        prop[8..].copy_from_slice(&(name_offset as u32).to_be_bytes());
+
+        // Properties come before subnodes. Replace an existing one, or add it in front.
+        let mut offset = properties;
+        let (at, remove) = loop {
+            let (token, next) = self.blob().token(offset)?;
+            match token {
+                FDT_PROP if self.blob().u32_at(offset + 8)? as usize == name_offset => {
+                    break (offset, next - offset)
+                }
+                FDT_PROP | FDT_NOP => offset = next,
+                _ => break (properties, 0),
+            }
+        };
+
+        let padding = [0u8; 3];
+        let padding = &padding[..align4(value.len()) - value.len()];
+        self.splice_struct(at, remove, &[&prop, value, padding])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assembles a blob: the header, an empty memory reservation block, the structure block and
+    /// the strings block.
+    struct Builder {
+        structure: [u8; 256],
+        struct_len: usize,
+        strings: [u8; 64],
+        strings_len: usize,
+    }
+
+    impl Builder {
+        fn new() -> Self {
+            Self { structure: [0; 256], struct_len: 0, strings: [0; 64], strings_len: 0 }
+        }
+
+        fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+            self.structure[self.struct_len..self.struct_len + bytes.len()].copy_from_slice(bytes);
+            self.struct_len = align4(self.struct_len + bytes.len());
+            self
+        }
+
+        fn begin(&mut self, name: &str) -> &mut Self {
+            self.bytes(&FDT_BEGIN_NODE.to_be_bytes()).bytes(name.as_bytes());
+            // The terminating NUL.
+            if name.len() % 4 == 0 {
+                self.bytes(&[0]);
+            }
+            self
+        }
+
+        fn end(&mut self) -> &mut Self {
+            self.bytes(&FDT_END_NODE.to_be_bytes())
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+            let name_offset = self.strings_len as u32;
+            self.strings[self.strings_len..self.strings_len + name.len()]
+                .copy_from_slice(name.as_bytes());
+            self.strings_len += name.len() + 1;
+
+            self.bytes(&FDT_PROP.to_be_bytes())
+                .bytes(&(value.len() as u32).to_be_bytes())
+                .bytes(&name_offset.to_be_bytes())
+                .bytes(value)
+        }
+
+        /// Write the blob to `out`, followed by `padding` bytes of free space, and return its
+        /// range.
+        fn build(&mut self, padding: usize, out: &mut [u8]) -> Range<usize> {
+            self.bytes(&FDT_END.to_be_bytes());
+
+            let off_dt_struct = HEADER_SIZE + 16;
+            let off_dt_strings = off_dt_struct + self.struct_len;
+            let total_size = off_dt_strings + self.strings_len + padding;
+            let header = [
+                FDT_MAGIC,
+                total_size as u32,
+                off_dt_struct as u32,
+                off_dt_strings as u32,
+                HEADER_SIZE as u32,
+                MIN_VERSION,
+                16,
+                0,
+                self.strings_len as u32,
+                self.struct_len as u32,
+            ];
+
+            out.fill(0);
+            for (i, field) in header.iter().enumerate() {
+                out[i * 4..i * 4 + 4].copy_from_slice(&field.to_be_bytes());
+            }
+            out[off_dt_struct..off_dt_strings].copy_from_slice(&self.structure[..self.struct_len]);
+            out[off_dt_strings..off_dt_strings + self.strings_len]
+                .copy_from_slice(&self.strings[..self.strings_len]);
+
+            let start = out.as_ptr() as usize;
+            start..start + total_size
+        }
+    }
+
+    fn be_cells(values: &[u32]) -> [u8; 16] {
+        let mut out = [0; 16];
+        for (i, x) in values.iter().enumerate() {
+            out[i * 4..i * 4 + 4].copy_from_slice(&x.to_be_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn memory_end_finds_the_bank_of_the_address() {
+        let mut out = [0u8; 512];
+        let reg = be_cells(&[0, 0, 0x3B40_0000, 0x4000_0000]);
+        let blob = Builder::new()
+            .begin("")
+            .prop("#address-cells", &2u32.to_be_bytes())
+            .prop("#size-cells", &1u32.to_be_bytes())
+            .begin("memory@0")
+            .prop("device_type", b"memory\0")
+            .prop("reg", &reg[..12])
+            .end()
+            .end()
+            .build(0, &mut out);
+
+        let end = unsafe { memory_end(&blob, 0x20_0000) };
+        assert!(matches!(end, Ok(0x3B40_0000)));
+    }
+
+    #[test]
+    fn memory_end_picks_among_several_banks() {
+        let mut out = [0u8; 512];
+        let reg = be_cells(&[0, 0x3B40_0000, 0x4000_0000, 0xBC00_0000]);
+        let blob = Builder::new()
+            .begin("")
+            .prop("#address-cells", &1u32.to_be_bytes())
+            .prop("#size-cells", &1u32.to_be_bytes())
+            .begin("memory")
+            .prop("reg", &reg)
+            .end()
+            .end()
+            .build(0, &mut out);
+
+        assert!(matches!(unsafe { memory_end(&blob, 0x20_0000) }, Ok(0x3B40_0000)));
+        assert!(matches!(unsafe { memory_end(&blob, 0x5000_0000) }, Ok(0xFC00_0000)));
+        assert!(matches!(unsafe { memory_end(&blob, 0x3C00_0000) }, Err(Error::Fdt(_))));
+    }
+
+    #[test]
+    fn memory_end_needs_a_memory_node() {
+        let mut out = [0u8; 512];
+        let blob = Builder::new().begin("").begin("chosen").end().end().build(0, &mut out);
+
+        assert!(matches!(unsafe { memory_end(&blob, 0x20_0000) }, Err(Error::Fdt(_))));
+    }
+
+    #[test]
+    fn free_space_after_the_strings_block_is_accepted() {
+        let mut out = [0u8; 512];
+        let reg = be_cells(&[0, 0x3B40_0000]);
+        let blob = Builder::new()
+            .begin("")
+            .prop("#address-cells", &1u32.to_be_bytes())
+            .prop("#size-cells", &1u32.to_be_bytes())
+            .begin("memory@0")
+            .prop("reg", &reg[..8])
+            .end()
+            .end()
+            .build(64, &mut out);
+
+        assert!(matches!(unsafe { memory_end(&blob, 0x20_0000) }, Ok(0x3B40_0000)));
+    }
+
+    /// The values of `name` in `/chosen`: the last one, and how many there are.
+    fn chosen_property<'a>(blob: &Blob<'a>, name: &str) -> (Option<&'a [u8]>, usize) {
+        let mut offset = blob.header(OFF_DT_STRUCT);
+        let (mut depth, mut in_chosen) = (0, false);
+        let (mut value, mut count) = (None, 0);
+
+        loop {
+            let Ok((token, next)) = blob.token(offset) else { panic!("bad structure block") };
+            match token {
+                FDT_BEGIN_NODE => {
+                    depth += 1;
+                    in_chosen = depth == 2 && blob.node_name(offset) == b"chosen";
+                }
+                FDT_PROP if in_chosen => match blob.property(offset) {
+                    Ok((x, v)) if x == name.as_bytes() => {
+                        value = Some(v);
+                        count += 1;
+                    }
+                    _ => {}
+                },
+                FDT_END_NODE => {
+                    depth -= 1;
+                    in_chosen = false;
+                }
+                FDT_END => return (value, count),
+                _ => {}
+            }
+            offset = next;
+        }
+    }
+
+    fn copy(blob: &Range<usize>, dst: &mut [u8]) -> Fdt {
+        match unsafe { Fdt::copy(blob, dst.as_mut_ptr(), dst.len()) } {
+            Ok(x) => x,
+            Err(_) => panic!("blob refused"),
+        }
+    }
+
+    #[test]
+    fn set_chosen_adds_the_node() {
+        let mut out = [0u8; 512];
+        let blob = Builder::new()
+            .begin("")
+            .begin("memory@0")
+            .prop("reg", &be_cells(&[0, 0x3B40_0000])[..8])
+            .end()
+            .end()
+            .build(0, &mut out);
+
+        let mut dst = [0u8; 1024];
+        let mut fdt = copy(&blob, &mut dst);
+        assert!(fdt.set_chosen("bootargs", b"console=ttyAMA0\0").is_ok());
+
+        assert!(fdt.blob().check().is_ok());
+        assert_eq!(fdt.range().start, dst.as_ptr() as usize);
+        assert_eq!(chosen_property(&fdt.blob(), "bootargs"), (Some(&b"console=ttyAMA0\0"[..]), 1));
+    }
+
+    #[test]
+    fn set_chosen_replaces_the_property() {
+        let mut out = [0u8; 512];
+        let blob = Builder::new()
+            .begin("")
+            .begin("chosen")
+            .prop("bootargs", b"quiet\0")
+            .prop("stdout-path", b"serial0\0")
+            .end()
+            .end()
+            .build(0, &mut out);
+
+        let mut dst = [0u8; 1024];
+        let mut fdt = copy(&blob, &mut dst);
+        let strings_size = fdt.header(SIZE_DT_STRINGS);
+        assert!(fdt.set_chosen("bootargs", b"console=ttyAMA0 rdinit=/init\0").is_ok());
+        let initrd_start = 0x2000_0000u64.to_be_bytes();
+        assert!(fdt.set_chosen("linux,initrd-start", &initrd_start).is_ok());
+
+        let blob = fdt.blob();
+        assert!(blob.check().is_ok());
+        assert_eq!(
+            chosen_property(&blob, "bootargs"),
+            (Some(&b"console=ttyAMA0 rdinit=/init\0"[..]), 1)
+        );
+        assert_eq!(chosen_property(&blob, "stdout-path"), (Some(&b"serial0\0"[..]), 1));
+        assert_eq!(chosen_property(&blob, "linux,initrd-start"), (Some(&initrd_start[..]), 1));
+
+        // Only the new name went to the strings block.
+        assert_eq!(blob.header(SIZE_DT_STRINGS), strings_size + "linux,initrd-start\0".len());
+    }
+
+    #[test]
+    fn set_chosen_stays_within_the_buffer() {
+        let mut out = [0u8; 512];
+        let blob = Builder::new().begin("").end().build(0, &mut out);
+
+        let mut dst = [0u8; 128];
+        let mut fdt = copy(&blob, &mut dst[..blob.len() + 16]);
+        assert!(matches!(fdt.set_chosen("bootargs", &[b'x'; 64]), Err(Error::Fdt(_))));
+    }
+}
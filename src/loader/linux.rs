@@ -0,0 +1,72 @@
+//! arm64 Linux `Image` files, see Documentation/arm64/booting.rst in the kernel tree.
+
+use core::ops::Range;
+use super::Error;
+
+const MAGIC: [u8; 4] = *b"ARM\x64";
+const MAGIC_OFFSET: usize = 0x38;
+const HEADER_SIZE: usize = 64;
+
+/// Header flag: the kernel is big-endian.
+const FLAG_BE: u64 = 1 << 0;
+
+/// The kernel runs at `text_offset` above a 2 MiB aligned base.
+const BASE_ALIGN: usize = 0x20_0000;
+
+/// `text_offset` of kernels before 3.17, whose header leaves `image_size` at zero.
+const LEGACY_TEXT_OFFSET: usize = 0x8_0000;
+
+fn u64_at(image: &[u8], offset: usize) -> u64 {
+    let mut raw = [0u8; 8];
+    raw.copy_from_slice(&image[offset..offset + 8]);
+    u64::from_le_bytes(raw)
+}
+
+pub fn is_image(image: &[u8]) -> bool {
+    image.get(MAGIC_OFFSET..MAGIC_OFFSET + MAGIC.len()) == Some(&MAGIC)
+}
+
+/// Where the kernel can run within `window`, if it fits.
+fn placement(window: &Range<usize>, text_offset: usize, size: usize) -> Option<Range<usize>> {
+    let base = (window.start.saturating_sub(text_offset) + BASE_ALIGN - 1) & !(BASE_ALIGN - 1);
+    let start = base.checked_add(text_offset)?;
+    let end = start.checked_add(size)?;
+
+    (end <= window.end).then_some(start..end)
+}
+
+/// Copy `image` to the first of `windows` it fits into and return the memory the kernel takes,
+/// which starts with its entry point.
+///
+/// The kernel may use more memory than `image` is long, as announced by `image_size` in its
+/// header. That memory is reserved, but not cleared.
+///
+/// # Safety
+///
+/// - `windows` must be unused RAM. `image` may overlap them.
+pub unsafe fn load(image: &[u8], windows: &[Range<usize>]) -> Result<Range<usize>, Error> {
+    if image.len() < HEADER_SIZE {
+        return Err(Error::Linux("truncated header"));
+    }
+    if u64_at(image, 0x18) & FLAG_BE != 0 {
+        return Err(Error::Linux("big-endian kernel"));
+    }
+
+    let (text_offset, size) = match u64_at(image, 0x10) as usize {
+        0 => (LEGACY_TEXT_OFFSET, image.len()),
+        x => (u64_at(image, 0x08) as usize, core::cmp::max(x, image.len())),
+    };
+
+    let range = windows
+        .iter()
+        .find_map(|x| placement(x, text_offset, size))
+        .ok_or(Error::TooBig {
+            size: size as u32,
+            max: windows.iter().map(|x| x.len()).max().unwrap_or(0),
+        })?;
+
+    // The image is usually staged at the top of the same window and may overlap.
+    core::ptr::copy(image.as_ptr(), range.start as *mut u8, image.len());
+
+    Ok(range)
+}
@@ -0,0 +1,231 @@
+//! Streaming LZ4 block decoder.
+//!
+//! Input can be fed in arbitrary pieces as it arrives from the host. Matches are resolved against
+//! the output that is already in place, so no window buffer or allocation is needed.
+
+use super::Error;
+
+enum State {
+    Token,
+    LiteralLength { len: usize, match_len: usize },
+    Literals { left: usize, match_len: usize },
+    OffsetLow { match_len: usize },
+    OffsetHigh { low: u8, match_len: usize },
+    MatchLength { offset: usize, len: usize },
+}
+
+/// Length nibble value that announces additional length bytes.
+const RUN_MASK: usize = 15;
+
+/// Shortest possible match, which is not encoded.
+const MIN_MATCH: usize = 4;
+
+pub struct Decoder {
+    dst: *mut u8,
+    capacity: usize,
+    pos: usize,
+    state: State,
+}
+
+impl Decoder {
+    /// Create a decoder writing to `dst`.
+    ///
+    /// # Safety
+    ///
+    /// - `dst` must be valid for reads and writes of `capacity` bytes.
+    pub unsafe fn new(dst: *mut u8, capacity: usize) -> Self {
+        Self {
+            dst,
+            capacity,
+            pos: 0,
+            state: State::Token,
+        }
+    }
+
+    fn put(&mut self, byte: u8) -> Result<(), Error> {
+        if self.pos == self.capacity {
+            return Err(Error::Decompress("output exceeds the announced size"));
+        }
+
+        unsafe { self.dst.add(self.pos).write(byte) };
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn copy_match(&mut self, offset: usize, len: usize) -> Result<(), Error> {
+        if offset == 0 || offset > self.pos {
+            return Err(Error::Decompress("match offset out of range"));
+        }
+        if len > self.capacity - self.pos {
+            return Err(Error::Decompress("output exceeds the announced size"));
+        }
+
+        // Byte by byte, since source and destination overlap for offsets shorter than the match.
+        for _ in 0..len {
+            unsafe { self.dst.add(self.pos).write(self.dst.add(self.pos - offset).read()) };
+            self.pos += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Decode the next piece of the compressed stream.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), Error> {
+        for &byte in data {
+            let byte_len = usize::from(byte);
+
+            self.state = match self.state {
+                State::Token => {
+                    let literals = byte_len >> 4;
+                    let match_len = byte_len & 0xF;
+
+                    match literals {
+                        0 => State::OffsetLow { match_len },
+                        RUN_MASK => State::LiteralLength { len: RUN_MASK, match_len },
+                        _ => State::Literals { left: literals, match_len },
+                    }
+                }
+                State::LiteralLength { len, match_len } => {
+                    let len = len + byte_len;
+
+                    if byte == 0xFF {
+                        State::LiteralLength { len, match_len }
+                    } else {
+                        State::Literals { left: len, match_len }
+                    }
+                }
+                State::Literals { left, match_len } => {
+                    self.put(byte)?;
+
+                    if left == 1 {
+                        State::OffsetLow { match_len }
+                    } else {
+                        State::Literals { left: left - 1, match_len }
+                    }
+                }
+                State::OffsetLow { match_len } => State::OffsetHigh { low: byte, match_len },
+                State::OffsetHigh { low, match_len } => {
+                    let offset = usize::from(u16::from_le_bytes([low, byte]));
+
+                    if match_len == RUN_MASK {
+                        State::MatchLength { offset, len: RUN_MASK + MIN_MATCH }
+                    } else {
+                        self.copy_match(offset, match_len + MIN_MATCH)?;
+                        State::Token
+                    }
+                }
+                State::MatchLength { offset, len } => {
+                    let len = len + byte_len;
+
+                    if byte == 0xFF {
+                        State::MatchLength { offset, len }
+                    } else {
+                        self.copy_match(offset, len)?;
+                        State::Token
+                    }
+                }
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Check that the stream ended on a sequence boundary and return the decompressed size.
+    pub fn finish(&self) -> Result<usize, Error> {
+        match self.state {
+            // The last sequence of a block carries literals only.
+            State::Token | State::OffsetLow { .. } => Ok(self.pos),
+            _ => Err(Error::Decompress("truncated stream")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three literals, then a match of nine bytes that overlaps its own output, then a literal.
+    const OVERLAPPING: &[u8] = &[0x35, b'a', b'b', b'c', 3, 0, 0x10, b'!'];
+
+    /// Decode `stream` fed in pieces of `piece` bytes into `out`.
+    fn decode(stream: &[u8], piece: usize, out: &mut [u8]) -> Result<usize, Error> {
+        let mut decoder = unsafe { Decoder::new(out.as_mut_ptr(), out.len()) };
+        for x in stream.chunks(piece) {
+            decoder.feed(x)?;
+        }
+        decoder.finish()
+    }
+
+    fn decompress_error(result: Result<usize, Error>) -> &'static str {
+        match result {
+            Err(Error::Decompress(x)) => x,
+            _ => panic!("expected a decompression error"),
+        }
+    }
+
+    #[test]
+    fn resolves_overlapping_matches() {
+        let mut out = [0u8; 16];
+        assert!(matches!(decode(OVERLAPPING, OVERLAPPING.len(), &mut out), Ok(13)));
+        assert_eq!(&out[..13], b"abcabcabcabc!");
+    }
+
+    #[test]
+    fn piece_boundaries_do_not_matter() {
+        for piece in 1..OVERLAPPING.len() {
+            let mut out = [0u8; 16];
+            assert!(matches!(decode(OVERLAPPING, piece, &mut out), Ok(13)));
+            assert_eq!(&out[..13], b"abcabcabcabc!");
+        }
+    }
+
+    #[test]
+    fn reads_additional_length_bytes() {
+        // 15 + 5 literals, then a match of 15 + 4 + 255 + 1 bytes.
+        let mut stream = [0u8; 27];
+        stream[..2].copy_from_slice(&[0xFF, 5]);
+        for (i, x) in stream[2..22].iter_mut().enumerate() {
+            *x = b'a' + i as u8;
+        }
+        stream[22..].copy_from_slice(&[1, 0, 0xFF, 1, 0x00]);
+
+        let mut out = [0u8; 300];
+        let result = decode(&stream[..26], 7, &mut out);
+        assert!(matches!(result, Ok(295)));
+        assert_eq!(out[19], b't');
+        assert!(out[20..295].iter().all(|&x| x == b't'));
+
+        // A final token without literals.
+        assert!(matches!(decode(&stream, 7, &mut out), Ok(295)));
+    }
+
+    #[test]
+    fn refuses_offsets_outside_the_output() {
+        let mut out = [0u8; 16];
+        let result = decode(&[0x10, b'a', 0, 0], 4, &mut out);
+        assert_eq!(decompress_error(result), "match offset out of range");
+
+        let result = decode(&[0x10, b'a', 2, 0], 4, &mut out);
+        assert_eq!(decompress_error(result), "match offset out of range");
+    }
+
+    #[test]
+    fn stops_at_the_announced_size() {
+        let mut out = [0u8; 12];
+        let result = decode(OVERLAPPING, 1, &mut out);
+        assert_eq!(decompress_error(result), "output exceeds the announced size");
+
+        let mut out = [0u8; 10];
+        let result = decode(OVERLAPPING, 1, &mut out);
+        assert_eq!(decompress_error(result), "output exceeds the announced size");
+    }
+
+    #[test]
+    fn refuses_truncated_streams() {
+        let mut out = [0u8; 16];
+        for len in [1, 3, 5] {
+            let result = decode(&OVERLAPPING[..len], 1, &mut out);
+            assert_eq!(decompress_error(result), "truncated stream");
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Manifests: several images, each loaded to an address of the host's choosing.
+//!
+//! A manifest is sent as the payload of a header with `FLAG_MANIFEST`. It is a list of regions of
+//! `REGION_SIZE` bytes each (all integers are little-endian): name (`NAME_LEN` bytes, padded with
+//! NULs), load address (u64), size (u32), CRC-32 of the region's contents (u32) and flags (u32,
+//! see `REGION_ENTRY`).
+//!
+//! Once the board has acknowledged the manifest, the host transfers the regions in the same order,
+//! each starting over at step 1 with a header of its own. They are unpacked straight to where they
+//! belong.
+
+use core::ops::Range;
+use super::Error;
+
+/// Longest region name.
+pub const NAME_LEN: usize = 16;
+
+/// Bytes per region in the manifest.
+pub const REGION_SIZE: usize = NAME_LEN + 20;
+
+/// Most regions a manifest may list.
+pub const MAX_REGIONS: usize = 16;
+
+/// Region flag: the region starts with code the board can jump to.
+pub const REGION_ENTRY: u32 = 1 << 0;
+
+/// An image the host places in memory.
+#[derive(Clone, Copy)]
+pub struct Region {
+    name: [u8; NAME_LEN],
+    pub start: usize,
+    pub size: usize,
+    pub crc: u32,
+    pub flags: u32,
+}
+
+impl Region {
+    const EMPTY: Self = Self { name: [0; NAME_LEN], start: 0, size: 0, crc: 0, flags: 0 };
+
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&x| x == 0).unwrap_or(NAME_LEN);
+        // Checked by `parse`.
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.start + self.size
+    }
+
+    pub fn is_entry(&self) -> bool {
+        self.flags & REGION_ENTRY != 0
+    }
+}
+
+/// The regions a manifest lists, in transfer order.
+pub struct Manifest {
+    regions: [Region; MAX_REGIONS],
+    len: usize,
+}
+
+impl Manifest {
+    pub fn regions(&self) -> &[Region] {
+        &self.regions[..self.len]
+    }
+
+    /// The regions that can be jumped to.
+    pub fn entries(&self) -> impl Iterator<Item = &Region> {
+        self.regions().iter().filter(|x| x.is_entry())
+    }
+}
+
+fn region(raw: &[u8]) -> Region {
+    let field = |offset: usize, len: usize| {
+        let mut bytes = [0u8; 8];
+        bytes[..len].copy_from_slice(&raw[offset..offset + len]);
+        u64::from_le_bytes(bytes)
+    };
+
+    let mut name = [0u8; NAME_LEN];
+    name.copy_from_slice(&raw[..NAME_LEN]);
+
+    Region {
+        name,
+        start: field(NAME_LEN, 8) as usize,
+        size: field(NAME_LEN + 8, 4) as usize,
+        crc: field(NAME_LEN + 12, 4) as u32,
+        flags: field(NAME_LEN + 16, 4) as u32,
+    }
+}
+
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// Parse the manifest in `raw`. Every region must lie within one of `windows` and must not
+/// overlap any other, and at least one must be an entry point.
+pub fn parse(raw: &[u8], windows: &[Range<usize>]) -> Result<Manifest, Error> {
+    let len = raw.len() / REGION_SIZE;
+    if raw.len() % REGION_SIZE != 0 || len == 0 || len > MAX_REGIONS {
+        return Err(Error::Manifest("wrong length"));
+    }
+
+    let mut manifest = Manifest { regions: [Region::EMPTY; MAX_REGIONS], len };
+    for (index, raw) in raw.chunks_exact(REGION_SIZE).enumerate() {
+        let region = region(raw);
+
+        if region.flags & !REGION_ENTRY != 0 {
+            return Err(Error::Manifest("unsupported region flags"));
+        }
+        if core::str::from_utf8(&region.name).is_err() {
+            return Err(Error::Manifest("region name is not UTF-8"));
+        }
+
+        let end = region.start.wrapping_add(region.size);
+        let bad = Error::Region { index, start: region.start, end };
+        let range = match region.start.checked_add(region.size) {
+            Some(end) if region.size > 0 => region.start..end,
+            _ => return Err(bad),
+        };
+        let fits = windows.iter().any(|x| x.start <= range.start && range.end <= x.end);
+        let taken = manifest.regions[..index].iter().any(|x| overlaps(&x.range(), &range));
+        if !fits || taken {
+            return Err(bad);
+        }
+
+        manifest.regions[index] = region;
+    }
+
+    if manifest.entries().next().is_none() {
+        return Err(Error::Manifest("no entry point"));
+    }
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOWS: [Range<usize>; 2] = [0x8_0000..0x100_0000, 0x200_0000..0x300_0000];
+
+    /// A manifest of up to three regions, each given as `(name, start, size, flags)`.
+    fn build(regions: &[(&str, usize, u32, u32)]) -> ([u8; 3 * REGION_SIZE], usize) {
+        let mut raw = [0u8; 3 * REGION_SIZE];
+        for (x, &(name, start, size, flags)) in raw.chunks_exact_mut(REGION_SIZE).zip(regions) {
+            x[..name.len()].copy_from_slice(name.as_bytes());
+            x[NAME_LEN..NAME_LEN + 8].copy_from_slice(&(start as u64).to_le_bytes());
+            x[NAME_LEN + 8..NAME_LEN + 12].copy_from_slice(&size.to_le_bytes());
+            x[NAME_LEN + 12..NAME_LEN + 16].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+            x[NAME_LEN + 16..].copy_from_slice(&flags.to_le_bytes());
+        }
+
+        (raw, regions.len() * REGION_SIZE)
+    }
+
+    fn parse_built(regions: &[(&str, usize, u32, u32)]) -> Result<Manifest, Error> {
+        let (raw, len) = build(regions);
+        parse(&raw[..len], &WINDOWS)
+    }
+
+    fn manifest_error(result: Result<Manifest, Error>) -> &'static str {
+        match result {
+            Err(Error::Manifest(x)) => x,
+            _ => panic!("expected a manifest error"),
+        }
+    }
+
+    fn region_error(result: Result<Manifest, Error>) -> usize {
+        match result {
+            Err(Error::Region { index, .. }) => index,
+            _ => panic!("expected a region error"),
+        }
+    }
+
+    #[test]
+    fn parses_regions_in_order() {
+        let result = parse_built(&[
+            ("kernel8.img", 0x8_0000, 0x1000, REGION_ENTRY),
+            ("bcm2711-rpi-4-b", 0x200_0000, 0x8000, 0),
+        ]);
+        let Ok(manifest) = result else { panic!("manifest refused") };
+
+        let regions = manifest.regions();
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0].name(), "kernel8.img");
+        assert_eq!(regions[0].range(), 0x8_0000..0x8_1000);
+        assert_eq!(regions[0].crc, 0x1234_5678);
+        assert_eq!(regions[1].name(), "bcm2711-rpi-4-b");
+        assert!(!regions[1].is_entry());
+
+        let mut entries = manifest.entries();
+        assert_eq!(entries.next().map(|x| x.start), Some(0x8_0000));
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn refuses_wrong_lengths() {
+        let (raw, len) = build(&[("a", 0x8_0000, 1, REGION_ENTRY)]);
+        assert_eq!(manifest_error(parse(&raw[..0], &WINDOWS)), "wrong length");
+        assert_eq!(manifest_error(parse(&raw[..len - 1], &WINDOWS)), "wrong length");
+
+        let raw = [0u8; (MAX_REGIONS + 1) * REGION_SIZE];
+        assert_eq!(manifest_error(parse(&raw, &WINDOWS)), "wrong length");
+    }
+
+    #[test]
+    fn refuses_unknown_flags_and_names() {
+        let result = parse_built(&[("a", 0x8_0000, 1, REGION_ENTRY | 2)]);
+        assert_eq!(manifest_error(result), "unsupported region flags");
+
+        let (mut raw, len) = build(&[("a", 0x8_0000, 1, REGION_ENTRY)]);
+        raw[1] = 0xFF;
+        assert_eq!(manifest_error(parse(&raw[..len], &WINDOWS)), "region name is not UTF-8");
+    }
+
+    #[test]
+    fn keeps_regions_within_the_windows() {
+        // Across the end of the first window.
+        let result = parse_built(&[("a", 0xFF_F000, 0x2000, REGION_ENTRY)]);
+        assert_eq!(region_error(result), 0);
+
+        // Empty, or wrapping around the address space.
+        let result = parse_built(&[("a", 0x8_0000, 1, REGION_ENTRY), ("b", 0x9_0000, 0, 0)]);
+        assert_eq!(region_error(result), 1);
+        let result = parse_built(&[("a", usize::MAX, 2, REGION_ENTRY)]);
+        assert_eq!(region_error(result), 0);
+
+        // Right up to the end of the second window.
+        let result = parse_built(&[("a", 0x2FF_F000, 0x1000, REGION_ENTRY)]);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn refuses_overlapping_regions() {
+        let result = parse_built(&[
+            ("a", 0x8_0000, 0x1000, REGION_ENTRY),
+            ("b", 0x8_1000, 0x1000, 0),
+            ("c", 0x8_1FFF, 0x10, 0),
+        ]);
+        assert_eq!(region_error(result), 2);
+    }
+
+    #[test]
+    fn needs_an_entry_point() {
+        let result = parse_built(&[("a", 0x8_0000, 0x1000, 0)]);
+        assert_eq!(manifest_error(result), "no entry point");
+    }
+}
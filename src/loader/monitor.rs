@@ -0,0 +1,276 @@
+//! Memory monitor: single commands on physical memory and MMIO registers, to look at a board
+//! without loading a payload.
+//!
+//! The host sends `MAGIC`, the command (u8), the access width in bytes (u8), an address (u64), an
+//! argument (u64) and the CRC-32 of all of it (u32), little-endian. The board answers `NAK` if the
+//! request is damaged, `CAN` if it refuses it (the reason follows on the console), and `ACK`
+//! followed by the result otherwise:
+//!
+//! - `PEEK`: reads `width` bytes at the address. Result: the value (u64) and its CRC-32 (u32).
+//! - `POKE`: writes the low `width` bytes of the argument to the address. No result.
+//! - `DUMP`: the argument is a length. Result: that many bytes from the address and their CRC-32
+//!   (u32).
+//! - `CRC`: the argument is a length. Result: the CRC-32 of that many bytes from the address (as
+//!   u64) and its CRC-32 (u32).
+//! - `JUMP`: no result. The board starts the code at the address like a payload.
+//! - `GPIO_GET`: the address is a GPIO pin, which becomes an input with the pull resistor the
+//!   argument selects (0: none, 1: up, 2: down). Result: its level (u64, 0 or 1) and the CRC-32
+//!   of that (u32).
+//! - `GPIO_SET`: the address is a GPIO pin, which becomes an output driven low (argument 0), high
+//!   (1), or to the opposite of its current level (2). No result.
+//! - `GPIO_ALT`: the address is a GPIO pin, which is handed to the peripheral behind alternate
+//!   function 0..=5, as given by the argument. No result.
+//! - `GPIO_WAIT`: the address is a GPIO pin among GPIO0..=27, which becomes an input. Bits 0..=7
+//!   of the argument select an event (see `EVENTS`), bits 32..=63 a timeout in milliseconds.
+//!   Result, once the event was delivered by the GPIO interrupt or the timeout passed: 1 or 0
+//!   (u64) and the CRC-32 of that (u32).
+//!
+//! Then the board requests a binary again, quietly. `PEEK` and `POKE` access all `width` bytes at
+//! once, as MMIO registers need. `DUMP` and `CRC` read byte by byte and are meant for RAM.
+//! Addresses are not checked: touching one where nothing is mapped hangs the board.
+//! The GPIO commands refuse pins the loader uses itself, like those of the console UART, and leave
+//! the pin as configured once they are done.
+//!
+//! Loaders built with `secure-load` do not listen to the monitor.
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use crate::bsp::gpio::{self, mode, Event, Function, Pin, Pull};
+use crate::{cpu, time};
+use super::{checksum, read_bytes, read_u32, send, Crc32, Error, ACK, NAK};
+
+/// Start of a monitor command: "MCMD".
+pub const MAGIC: [u8; 4] = *b"MCMD";
+
+pub const PEEK: u8 = 1;
+pub const POKE: u8 = 2;
+pub const DUMP: u8 = 3;
+pub const CRC: u8 = 4;
+pub const JUMP: u8 = 5;
+pub const GPIO_GET: u8 = 6;
+pub const GPIO_SET: u8 = 7;
+pub const GPIO_ALT: u8 = 8;
+pub const GPIO_WAIT: u8 = 9;
+
+/// `GPIO_GET` arguments.
+const PULLS: [Pull; 3] = [Pull::None, Pull::Up, Pull::Down];
+
+/// `GPIO_SET` arguments.
+const LEVEL_LOW: u64 = 0;
+const LEVEL_HIGH: u64 = 1;
+const LEVEL_TOGGLE: u64 = 2;
+
+/// `GPIO_ALT` arguments.
+const ALT_FUNCTIONS: [Function; 6] = [
+    Function::Alt0,
+    Function::Alt1,
+    Function::Alt2,
+    Function::Alt3,
+    Function::Alt4,
+    Function::Alt5,
+];
+
+/// Bytes in a command, including the CRC.
+const COMMAND_SIZE: usize = 26;
+
+fn u64_at(raw: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&raw[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// # Safety
+///
+/// - `addr` must be aligned to `width`, which must be 1, 2, 4 or 8.
+unsafe fn read(addr: usize, width: u8) -> u64 {
+    match width {
+        1 => u64::from(ptr::read_volatile(addr as *const u8)),
+        2 => u64::from(ptr::read_volatile(addr as *const u16)),
+        4 => u64::from(ptr::read_volatile(addr as *const u32)),
+        _ => ptr::read_volatile(addr as *const u64),
+    }
+}
+
+/// # Safety
+///
+/// - `addr` must be aligned to `width`, which must be 1, 2, 4 or 8.
+unsafe fn write(addr: usize, width: u8, value: u64) {
+    match width {
+        1 => ptr::write_volatile(addr as *mut u8, value as u8),
+        2 => ptr::write_volatile(addr as *mut u16, value as u16),
+        4 => ptr::write_volatile(addr as *mut u32, value as u32),
+        _ => ptr::write_volatile(addr as *mut u64, value),
+    }
+}
+
+fn send_value(value: u64) {
+    let raw = value.to_le_bytes();
+    for &b in raw.iter().chain(checksum(&raw).to_le_bytes().iter()) {
+        send(b);
+    }
+}
+
+/// The `len` bytes at `addr`, one volatile read each.
+fn bytes(addr: usize, len: usize) -> impl Iterator<Item = u8> {
+    (addr..addr + len).map(|x| unsafe { ptr::read_volatile(x as *const u8) })
+}
+
+/// `GPIO_WAIT` events.
+const EVENTS: [Event; 6] = [
+    Event::RisingEdge,
+    Event::FallingEdge,
+    Event::High,
+    Event::Low,
+    Event::AsyncRisingEdge,
+    Event::AsyncFallingEdge,
+];
+
+/// Set from the GPIO interrupt once the pin of a `GPIO_WAIT` saw its event.
+static GPIO_EVENT: AtomicBool = AtomicBool::new(false);
+
+fn note_gpio_event(_pin: u8) {
+    GPIO_EVENT.store(true, Ordering::Relaxed);
+}
+
+/// Wait up to `timeout` for `note_gpio_event`. Returns whether it was called.
+fn wait_for_gpio_event(timeout: Duration) -> bool {
+    let deadline = time::time_manager().uptime() + timeout;
+
+    while !GPIO_EVENT.load(Ordering::Relaxed) {
+        if time::time_manager().uptime() >= deadline {
+            return false;
+        }
+        cpu::nop();
+    }
+    true
+}
+
+fn claim_pin(addr: usize) -> Result<Pin<mode::Unconfigured>, Error> {
+    let number = u8::try_from(addr).map_err(|_| Error::Monitor("no such GPIO pin"))?;
+
+    gpio::claim(number).map_err(Error::Monitor)
+}
+
+/// Carry out the GPIO command `command` on `pin`. `arg` was checked by `serve`.
+fn serve_gpio(command: u8, pin: Pin<mode::Unconfigured>, arg: u64) {
+    match command {
+        GPIO_GET => {
+            let pin = pin.into_input();
+            pin.set_pull(PULLS[arg as usize]);
+            send_value(u64::from(pin.is_high()));
+        }
+        GPIO_SET => {
+            let pin = pin.into_output();
+            match arg {
+                LEVEL_LOW => pin.set_low(),
+                LEVEL_HIGH => pin.set_high(),
+                _ => pin.toggle(),
+            }
+        }
+        _ => {
+            pin.into_alt(ALT_FUNCTIONS[arg as usize]);
+        }
+    }
+}
+
+/// Serve a monitor command, whose first byte was consumed by `receive_start`. Returns the address
+/// to jump to for `JUMP`.
+pub fn serve() -> Result<Option<usize>, Error> {
+    let mut raw = [0u8; COMMAND_SIZE];
+    raw[0] = MAGIC[0];
+    read_bytes(&mut raw[1..COMMAND_SIZE - 4])?;
+    let crc = read_u32()?;
+
+    let body = &raw[..COMMAND_SIZE - 4];
+    if body[..4] != MAGIC || checksum(body) != crc {
+        // The host asks again.
+        send(NAK);
+        return Ok(None);
+    }
+
+    let (command, width) = (body[4], body[5]);
+    let addr = u64_at(body, 6) as usize;
+    let arg = u64_at(body, 14);
+
+    match command {
+        PEEK | POKE => {
+            if !matches!(width, 1 | 2 | 4 | 8) {
+                return Err(Error::Monitor("access width must be 1, 2, 4 or 8 bytes"));
+            }
+            if addr % usize::from(width) != 0 {
+                return Err(Error::Monitor("address not aligned to the access width"));
+            }
+        }
+        DUMP | CRC => {
+            if addr.checked_add(arg as usize).is_none() {
+                return Err(Error::Monitor("range wraps around"));
+            }
+        }
+        JUMP => {}
+        GPIO_GET if arg as usize >= PULLS.len() => {
+            return Err(Error::Monitor("pull must be 0 (none), 1 (up) or 2 (down)"));
+        }
+        GPIO_SET if arg > LEVEL_TOGGLE => {
+            return Err(Error::Monitor("level must be 0 (low), 1 (high) or 2 (toggle)"));
+        }
+        GPIO_ALT if arg as usize >= ALT_FUNCTIONS.len() => {
+            return Err(Error::Monitor("alternate function must be 0 to 5"));
+        }
+        GPIO_WAIT if (arg & 0xff) as usize >= EVENTS.len() => {
+            return Err(Error::Monitor("event must be 0 to 5"));
+        }
+        GPIO_GET | GPIO_SET | GPIO_ALT | GPIO_WAIT => {}
+        _ => return Err(Error::Monitor("unknown command")),
+    }
+
+    if command == GPIO_WAIT {
+        let pin = claim_pin(addr)?.into_input();
+        GPIO_EVENT.store(false, Ordering::Relaxed);
+        pin.listen(EVENTS[(arg & 0xff) as usize], note_gpio_event).map_err(Error::Monitor)?;
+
+        send(ACK);
+        let seen = wait_for_gpio_event(Duration::from_millis(arg >> 32));
+        pin.unlisten();
+        send_value(u64::from(seen));
+
+        return Ok(None);
+    }
+
+    // Claimed before the command is accepted, so that a pin in use is refused.
+    let pin = match command {
+        GPIO_GET | GPIO_SET | GPIO_ALT => Some(claim_pin(addr)?),
+        _ => None,
+    };
+
+    send(ACK);
+    if let Some(pin) = pin {
+        serve_gpio(command, pin, arg);
+        return Ok(None);
+    }
+
+    match command {
+        PEEK => send_value(unsafe { read(addr, width) }),
+        POKE => unsafe { write(addr, width, arg) },
+        DUMP => {
+            let mut crc = Crc32::new();
+            for b in bytes(addr, arg as usize) {
+                crc.update(&[b]);
+                send(b);
+            }
+            for b in crc.finish().to_le_bytes() {
+                send(b);
+            }
+        }
+        CRC => {
+            let mut crc = Crc32::new();
+            for b in bytes(addr, arg as usize) {
+                crc.update(&[b]);
+            }
+            send_value(u64::from(crc.finish()));
+        }
+        _ => return Ok(Some(addr)),
+    }
+
+    Ok(None)
+}
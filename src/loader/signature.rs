@@ -0,0 +1,32 @@
+//! Ed25519 signatures on payloads, checked when built with the `secure-load` feature.
+//!
+//! A signed image ends with the signature over everything before it, followed by `MAGIC`. The
+//! public key is read at build time from the file named by `SECURE_LOAD_PUBLIC_KEY`, 32 raw bytes
+//! as written by `minipush keygen`.
+
+use ed25519_compact::{PublicKey, Signature};
+use super::Error;
+
+/// End of a signed image: "RPSG".
+const MAGIC: [u8; 4] = *b"RPSG";
+
+/// The key payloads must be signed with.
+static PUBLIC_KEY: &[u8; PublicKey::BYTES] = include_bytes!(env!("SECURE_LOAD_PUBLIC_KEY"));
+
+/// XMODEM pads the last packet with SUB, which `MAGIC` does not end with.
+const XMODEM_PADDING: u8 = 0x1A;
+
+/// Check the signature of `image` and return the image without it.
+pub fn verify(image: &'static [u8]) -> Result<&'static [u8], Error> {
+    let end = image.iter().rposition(|&x| x != XMODEM_PADDING).map_or(0, |x| x + 1);
+    let signed = image[..end].strip_suffix(&MAGIC).ok_or(Error::Unsigned)?;
+    let len = signed.len().checked_sub(Signature::BYTES).ok_or(Error::Unsigned)?;
+    let (body, signature) = signed.split_at(len);
+
+    let signature = Signature::from_slice(signature).map_err(|_| Error::Unsigned)?;
+    PublicKey::new(*PUBLIC_KEY)
+        .verify(body, &signature)
+        .map_err(|_| Error::Signature)?;
+
+    Ok(body)
+}
@@ -0,0 +1,200 @@
+//! XMODEM-CRC, XMODEM-1K and YMODEM receiver, for hosts that only have a terminal program such as
+//! minicom, picocom or tio.
+//!
+//! Only the CRC-16 variants are supported. A YMODEM batch is cut short after its first file.
+
+use core::time::Duration;
+use crate::console::console;
+use super::{send, Error, ACK, CAN, MAX_RETRIES, NAK, SOH};
+
+pub const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+
+/// Sent by the receiver to start a transfer in CRC mode.
+pub const CRC_MODE: u8 = b'C';
+
+/// How long a packet may pause in the middle before it is considered damaged.
+const BYTE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long to wait for the next packet. Senders retransmit after 10 seconds.
+const PACKET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// CRC-16/XMODEM: polynomial 0x1021, initial value 0, MSB first.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+
+    crc
+}
+
+fn read_byte(timeout: Duration) -> Option<u8> {
+    console().read_char_timeout(timeout).map(|c| c as u8)
+}
+
+/// Drop the rest of a damaged packet.
+fn purge() {
+    while read_byte(BYTE_TIMEOUT).is_some() {}
+}
+
+enum Packet {
+    Data { number: u8, len: usize },
+    End,
+    Cancelled,
+}
+
+/// Read one packet into `buf`, starting with its already received header byte `start`.
+///
+/// Returns `None` if the packet was damaged and has to be sent again.
+fn read_packet(start: u8, buf: &mut [u8; 1024]) -> Option<Packet> {
+    let len = match start {
+        SOH => 128,
+        STX => 1024,
+        EOT => return Some(Packet::End),
+        // A single CAN may be line noise, senders cancel with two.
+        CAN => return read_byte(BYTE_TIMEOUT).filter(|&x| x == CAN).map(|_| Packet::Cancelled),
+        _ => return None,
+    };
+
+    let number = read_byte(BYTE_TIMEOUT)?;
+    let complement = read_byte(BYTE_TIMEOUT)?;
+    for b in buf[..len].iter_mut() {
+        *b = read_byte(BYTE_TIMEOUT)?;
+    }
+    let crc = u16::from_be_bytes([read_byte(BYTE_TIMEOUT)?, read_byte(BYTE_TIMEOUT)?]);
+
+    if number != !complement || crc != crc16(&buf[..len]) {
+        return None;
+    }
+
+    Some(Packet::Data { number, len })
+}
+
+/// Parse the file size out of a YMODEM block 0: `name\0size [mtime ...]\0`.
+fn ymodem_size(block: &[u8]) -> Option<usize> {
+    let name_end = block.iter().position(|&b| b == 0)?;
+    let size = block[name_end + 1..].iter().take_while(|b| b.is_ascii_digit());
+
+    let mut value: usize = 0;
+    for digit in size {
+        value = value.checked_mul(10)?.checked_add(usize::from(digit - b'0'))?;
+    }
+
+    Some(value)
+}
+
+/// Receive a file to `dst`, which has room for `max` bytes, and return its size.
+///
+/// `start` is the first packet's header byte, which the caller already read while waiting for the
+/// host. A YMODEM sender announces the file size in block 0, which is honored. For plain XMODEM,
+/// the size includes the padding of the last packet.
+///
+/// # Safety
+///
+/// - `dst` must be valid for writes of `max` bytes.
+pub unsafe fn receive(start: u8, dst: *mut u8, max: usize) -> Result<usize, Error> {
+    let mut buf = [0u8; 1024];
+    let mut next: u8 = 1;
+    let mut offset: usize = 0;
+    let mut file_size: Option<usize> = None;
+    let mut ymodem = false;
+    let mut eot_seen = false;
+    let mut errors = 0;
+    let mut start = Some(start);
+
+    loop {
+        let header = match start.take().or_else(|| read_byte(PACKET_TIMEOUT)) {
+            Some(x) => x,
+            None => {
+                errors += 1;
+                if errors == MAX_RETRIES {
+                    return Err(Error::Timeout);
+                }
+                send(NAK);
+                continue;
+            }
+        };
+
+        let packet = match read_packet(header, &mut buf) {
+            Some(x) => x,
+            None => {
+                errors += 1;
+                if errors == MAX_RETRIES {
+                    return Err(Error::Block { index: u32::from(next) });
+                }
+                purge();
+                send(NAK);
+                continue;
+            }
+        };
+        errors = 0;
+
+        let (number, len) = match packet {
+            Packet::Cancelled => return Err(Error::Cancelled),
+            Packet::End if ymodem && !eot_seen => {
+                // YMODEM senders expect the first EOT to be refused.
+                eot_seen = true;
+                send(NAK);
+                continue;
+            }
+            Packet::End => {
+                send(ACK);
+                break;
+            }
+            Packet::Data { number, len } => (number, len),
+        };
+
+        // Block 0 at the very beginning is a YMODEM file header.
+        if number == 0 && offset == 0 && !ymodem {
+            ymodem = true;
+            if buf[0] == 0 {
+                // Empty batch.
+                send(ACK);
+                return Err(Error::Cancelled);
+            }
+
+            let size = ymodem_size(&buf[..len]).ok_or(Error::Xmodem("bad file header"))?;
+            if size > max {
+                return Err(Error::TooBig { size: size as u32, max });
+            }
+            file_size = Some(size);
+
+            send(ACK);
+            send(CRC_MODE);
+            continue;
+        }
+
+        // Our ACK got lost and the sender repeated the previous packet.
+        if number == next.wrapping_sub(1) {
+            send(ACK);
+            continue;
+        }
+        if number != next {
+            return Err(Error::Xmodem("packet out of sequence"));
+        }
+
+        if offset + len > max {
+            return Err(Error::TooBig { size: (offset + len) as u32, max });
+        }
+        core::ptr::copy_nonoverlapping(buf.as_ptr(), dst.add(offset), len);
+        offset += len;
+        next = next.wrapping_add(1);
+        send(ACK);
+    }
+
+    if ymodem {
+        // Close the batch by asking for the next file header. An empty one ends the batch, any
+        // further file is cancelled.
+        send(CRC_MODE);
+        match read_byte(PACKET_TIMEOUT).and_then(|x| read_packet(x, &mut buf)) {
+            Some(Packet::Data { number: 0, .. }) if buf[0] == 0 => send(ACK),
+            _ => super::abort(),
+        }
+    }
+
+    Ok(file_size.map_or(offset, |size| core::cmp::min(size, offset)))
+}
@@ -10,16 +10,32 @@
 #![no_main]
 #![no_std]
 
+use core::ops::Range;
+use core::time::Duration;
+use loader::manifest::Manifest;
+
 mod bsp;
 mod console;
 mod cpu;
 mod driver;
+mod exception;
+mod loader;
+mod memory;
 mod panic_wait;
 mod print;
+mod state;
 mod synchronization;
+mod time;
 
 /// init kernel
-pub unsafe fn kernel_init()->!{
+pub unsafe fn kernel_init(boot_args: cpu::boot::BootArgs)->!{
+    exception::handling_init();
+
+    // The caches make loading faster, and `SpinLock` needs them.
+    if let Err(x) = memory::mmu::enable_mmu_and_caching() {
+        panic!("MMU: {}", x);
+    }
+
     // Initialize the BSP driver subsystem.
     if let Err(x) = bsp::driver::init() {
         panic!("Error initializing BSP driver subsystem: {}", x);
@@ -29,8 +45,19 @@ pub unsafe fn kernel_init()->!{
     driver::driver_manager().init_drivers();
     // println! is usable from here on.
 
+    exception::asynchronous::irq_manager().print_handler();
+
+    // Nothing gets registered any more.
+    state::state_manager().transition_to_single_core_main();
+
+    // Let device interrupts through now that their handlers are registered.
+    exception::asynchronous::local_irq_unmask();
+
+    // The firmware passes the device tree in x0, and so does a payload that comes back.
+    let dtb = loader::fdt::blob_range(boot_args.x0 as usize);
+
     // Transition from unsafe to safe.
-    kernel_main()
+    kernel_main(dtb, boot_args.reentered)
 }
 
 const MINILOAD_LOGO: &str = r#"
@@ -40,47 +67,496 @@ const MINILOAD_LOGO: &str = r#"
 |_|  |_|_|_||_|_|____\___/\__,_\__,_|
 "#;
 
-fn kernel_main() -> ! {
+/// What the console runs at until the host asks for something else, and when a payload starts.
+const DEFAULT_LINE: loader::baud::Setting = loader::baud::Setting {
+    baud: bsp::console::DEFAULT_BAUD_RATE,
+    line: bsp::console::LineConfig::DEFAULT,
+};
+
+/// Time the host gets to return to the default line setting once the payload is in.
+const BAUD_RESTORE_DELAY: Duration = Duration::from_millis(100);
+
+/// How long the boot menu waits for a choice before it starts the first entry point.
+const MENU_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Room for what the device tree gains for Linux: the command line, the initramfs location and
+/// possibly a `/chosen` node.
+const FDT_GROWTH: usize = loader::BLOCK_SIZE + 256;
+
+/// Where payloads may go, with the device tree cut out.
+struct Memory {
+    /// Below the loader, starting at the board's default load address.
+    low: Range<usize>,
+    /// Above the loader. Payloads are received at its top.
+    high: Range<usize>,
+}
+
+/// Cut `reserved` out of `window` and keep the bigger part.
+fn exclude(window: Range<usize>, reserved: &Range<usize>) -> Range<usize> {
+    if reserved.start >= window.end || window.start >= reserved.end {
+        return window;
+    }
+
+    let below = window.start..core::cmp::max(window.start, reserved.start);
+    let above = core::cmp::min(window.end, reserved.end)..window.end;
+    if below.len() >= above.len() {
+        below
+    } else {
+        above
+    }
+}
+
+/// The buffer the command line is received into.
+///
+/// In the loader's `.bss` rather than on the stack, so that what x1 points to is part of
+/// `bsp::memory::loader_footprint`, which the payload has to leave alone anyway.
+static mut CMDLINE: [u8; loader::BLOCK_SIZE] = [0; loader::BLOCK_SIZE];
+
+/// What the payload gets besides its entry point.
+struct Handoff {
+    /// The device tree blob: the firmware's, or a copy prepared for Linux.
+    dtb: Option<Range<usize>>,
+    /// NUL-terminated command line, valid if `cmdline_len` is set.
+    cmdline: &'static mut [u8; loader::BLOCK_SIZE],
+    cmdline_len: Option<usize>,
+    /// Memory taken by the payload if it is a Linux `Image`.
+    linux: Option<Range<usize>>,
+}
+
+impl Handoff {
+    /// Registers x0..x3 for the payload.
+    ///
+    /// As in the Linux arm64 boot protocol, x0 is the physical address of the device tree blob,
+    /// or 0 if there is none. For other payloads, x1 points to the command line if the host sent
+    /// one, and is 0 otherwise, and x2 holds the re-entry vector (see
+    /// `bsp::memory::reentry_vector`). Linux finds the command line in the device tree instead and
+    /// gets 0 in x1 and x2. x3 is 0.
+    fn registers(&self) -> [u64; 4] {
+        let dtb = self.dtb.as_ref().map_or(0, |x| x.start as u64);
+        if self.linux.is_some() {
+            return [dtb, 0, 0, 0];
+        }
+
+        let cmdline = match self.cmdline_len {
+            Some(_) => self.cmdline.as_ptr() as u64,
+            None => 0,
+        };
+
+        [dtb, cmdline, bsp::memory::reentry_vector() as u64, 0]
+    }
+}
+
+/// What arrived, ready to run.
+enum Loaded {
+    /// A single image with its entry point.
+    Image(usize),
+    /// The regions of a manifest, of which the user picks an entry point.
+    Manifest(Manifest),
+}
+
+/// Move a received image to where it runs and return its entry point.
+fn place_payload(
+    image: &'static [u8],
+    memory: &Memory,
+    handoff: &mut Handoff,
+) -> Result<usize, loader::Error> {
+    handoff.linux = None;
+
+    if loader::elf::is_elf(image) {
+        let staging = image.as_ptr() as usize..image.as_ptr() as usize + image.len();
+        let dtb = handoff.dtb.clone().unwrap_or(0..0);
+        let windows = [memory.low.clone(), memory.high.clone()];
+        let reserved = [bsp::memory::loader_footprint(), staging, dtb];
+
+        return unsafe { loader::elf::load(image, &windows, &reserved) };
+    }
+
+    if loader::linux::is_image(image) {
+        let windows = [memory.low.clone(), memory.high.clone()];
+        let kernel = unsafe { loader::linux::load(image, &windows)? };
+        let entry = kernel.start;
+        handoff.linux = Some(kernel);
+
+        return Ok(entry);
+    }
+
+    // A flat binary runs from the board's default load address.
+    let kernel_addr: *mut u8 = bsp::memory::board_default_load_addr() as *mut u8;
+    let max = memory.low.end.saturating_sub(kernel_addr as usize);
+    if kernel_addr as usize + image.len() > memory.low.end {
+        return Err(loader::Error::TooBig { size: image.len() as u32, max });
+    }
+    unsafe { core::ptr::copy(image.as_ptr(), kernel_addr, image.len()) };
+
+    Ok(kernel_addr as usize)
+}
+
+/// Get the Linux kernel placed at `kernel` ready to boot: receive the initramfs if `initrd` is
+/// set, then tell the kernel about it and the command line through `/chosen` of the device tree.
+fn prepare_linux(
+    kernel: &Range<usize>,
+    initrd: bool,
+    memory: &Memory,
+    handoff: &mut Handoff,
+) -> Result<(), loader::Error> {
+    let dtb = handoff.dtb.clone().ok_or(loader::Error::Linux("no device tree from the firmware"))?;
+
+    // What is left of the memory payloads are received in.
+    let mut free = memory.high.clone();
+    if free.contains(&kernel.start) {
+        free.start = kernel.end;
+    }
+
+    let capacity = dtb.len() + FDT_GROWTH;
+    let mut initrd_range = None;
+    if initrd {
+        // The kernel is in place. The initramfs follows with a header of its own.
+        loader::confirm();
+
+        let header = loader::receive_header()?;
+        if header.flags & !loader::FLAG_LZ4 != 0 {
+            return Err(loader::Error::Flags(header.flags & !loader::FLAG_LZ4));
+        }
+        loader::accept_header(&header, free.len().saturating_sub(capacity))?;
+
+        let staging = loader::staging_addr(&free, header.image_size)?;
+        let image = unsafe { loader::receive_payload(&header, staging)? };
+        let image = loader::authenticate(image)?;
+        let range = image.as_ptr() as usize..image.as_ptr() as usize + image.len();
+        free.end = range.start;
+        initrd_range = Some(range);
+    }
+
+    // Nothing to add. The kernel gets the firmware's device tree as is.
+    if initrd_range.is_none() && handoff.cmdline_len.is_none() {
+        return Ok(());
+    }
+
+    let dst = free.end.saturating_sub(capacity) & !7;
+    if dst < free.start {
+        return Err(loader::Error::Fdt("no room for the device tree"));
+    }
+
+    let mut fdt = unsafe { loader::fdt::Fdt::copy(&dtb, dst as *mut u8, capacity)? };
+    if let Some(len) = handoff.cmdline_len {
+        // Including the terminating NUL.
+        fdt.set_chosen("bootargs", &handoff.cmdline[..=len])?;
+    }
+    if let Some(range) = initrd_range {
+        fdt.set_chosen("linux,initrd-start", &(range.start as u64).to_be_bytes())?;
+        fdt.set_chosen("linux,initrd-end", &(range.end as u64).to_be_bytes())?;
+    }
+    handoff.dtb = Some(fdt.range());
+
+    Ok(())
+}
+
+/// Receive the regions the manifest in `raw` lists, each straight to its address.
+fn receive_regions(raw: &[u8], memory: &Memory) -> Result<Manifest, loader::Error> {
+    let windows = [memory.low.clone(), memory.high.clone()];
+    let manifest = loader::manifest::parse(raw, &windows)?;
+
+    for region in manifest.regions() {
+        // The manifest or the previous region is in. The next one follows with a header of its
+        // own.
+        loader::confirm();
+
+        let header = loader::receive_header()?;
+        if header.flags & !loader::FLAG_LZ4 != 0 {
+            return Err(loader::Error::Flags(header.flags & !loader::FLAG_LZ4));
+        }
+        if header.image_size as usize != region.size || header.crc != region.crc {
+            return Err(loader::Error::Manifest("region header does not match the manifest"));
+        }
+        loader::accept_header(&header, region.size)?;
+
+        unsafe { loader::receive_payload(&header, region.start as *mut u8)? };
+    }
+
+    Ok(manifest)
+}
+
+/// Receive a payload from `Minipush`.
+fn receive_minipush(memory: &Memory, handoff: &mut Handoff) -> Result<Loaded, loader::Error> {
+    let header = loader::receive_header()?;
+
+    // Refuse anything that would run into the loader itself.
+    loader::accept_header(&header, memory.high.len())?;
+
+    handoff.cmdline_len = None;
+    if header.flags & loader::FLAG_CMDLINE != 0 {
+        handoff.cmdline_len = Some(loader::receive_cmdline(handoff.cmdline)?);
+    }
+
+    let staging = loader::staging_addr(&memory.high, header.image_size)?;
+    let image = unsafe { loader::receive_payload(&header, staging)? };
+    let initrd = header.flags & loader::FLAG_INITRD != 0;
+
+    if header.flags & loader::FLAG_MANIFEST != 0 {
+        if initrd {
+            return Err(loader::Error::Manifest("initramfs with a manifest"));
+        }
+        handoff.linux = None;
+        let manifest = receive_regions(image, memory)?;
+        loader::confirm();
+
+        return Ok(Loaded::Manifest(manifest));
+    }
+
+    let image = loader::authenticate(image)?;
+    let entry = place_payload(image, memory, handoff)?;
+    match handoff.linux.clone() {
+        Some(kernel) => prepare_linux(&kernel, initrd, memory, handoff)?,
+        None if initrd => return Err(loader::Error::Linux("initramfs without a Linux Image")),
+        None => {}
+    }
+    loader::confirm();
+
+    Ok(Loaded::Image(entry))
+}
+
+/// Receive a payload from an XMODEM or YMODEM sender.
+fn receive_xmodem(
+    start: u8,
+    memory: &Memory,
+    handoff: &mut Handoff,
+) -> Result<Loaded, loader::Error> {
+    let load_addr = memory.high.start as *mut u8;
+    let size = unsafe { loader::xmodem::receive(start, load_addr, memory.high.len())? };
+
+    // The size is only known now. Move the image out of the way of ELF segments.
+    let staging = loader::staging_addr(&memory.high, size as u32)?;
+    let image = unsafe {
+        core::ptr::copy(load_addr, staging, size);
+        core::slice::from_raw_parts(staging, size)
+    };
+    let image = loader::authenticate(image)?;
+
+    // Terminal programs have no way to send a command line.
+    handoff.cmdline_len = None;
+
+    let entry = place_payload(image, memory, handoff)?;
+    if let Some(kernel) = handoff.linux.clone() {
+        prepare_linux(&kernel, false, memory, handoff)?;
+    }
+
+    Ok(Loaded::Image(entry))
+}
+
+/// Reconfigure the console UART for `setting`.
+fn switch_line(setting: loader::baud::Setting) -> Result<(), &'static str> {
+    bsp::console::set_line_config(setting.line)?;
+    bsp::console::set_baud_rate(setting.baud)
+}
+
+/// Report receive errors of the console UART, which point at the cable or the baud rate rather
+/// than the host. Counted since boot, or since the payload came back.
+fn print_line_errors() {
+    let console = console::console();
+    let (framing, parity) = (console.framing_errors(), console.parity_errors());
+    let (breaks, overruns) = (console.break_conditions(), console.overrun_errors());
+    if framing + parity + breaks + overruns > 0 {
+        println!(
+            "[ML] Line errors: {} framing, {} parity, {} break, {} overrun",
+            framing, parity, breaks, overruns
+        );
+    }
+}
+
+/// List the regions of `manifest` and return the entry point to start. With more than one, the
+/// user picks it on the console.
+fn boot_menu(manifest: &Manifest) -> usize {
+    use console::console;
+
+    for region in manifest.regions() {
+        let kind = if region.is_entry() { ", entry point" } else { "" };
+        println!("[ML] {} at {:#x}..{:#x}{}", region.name(), region.start, region.range().end, kind);
+    }
+
+    // `parse` made sure there is one.
+    let first = manifest.entries().next().map_or(0, |x| x.start);
+    let count = manifest.entries().count();
+    if count == 1 {
+        return first;
+    }
+
+    // Keys 1-9, then a, b, ... for the rest.
+    println!("[ML] Entry points:");
+    for (key, region) in (1..).filter_map(|x| char::from_digit(x, 36)).zip(manifest.entries()) {
+        println!("[ML]   {}) {}", key, region.name());
+    }
+    print!("[ML] Start which one? Starting the first in {} s: ", MENU_TIMEOUT.as_secs());
+
+    // Keys that are ignored do not restart the countdown.
+    let deadline = time::time_manager().uptime() + MENU_TIMEOUT;
+    let index = loop {
+        let left = deadline.saturating_sub(time::time_manager().uptime());
+        match console().read_char_timeout(left) {
+            // Enter, or no answer at all.
+            None | Some('\r') | Some('\n') => break 0,
+            Some(c) => match c.to_digit(36).map(|x| x as usize) {
+                Some(x) if (1..=count).contains(&x) => break x - 1,
+                // Anything else is ignored.
+                _ => {}
+            },
+        }
+    };
+
+    // `index` is below `count`.
+    let region = manifest.entries().nth(index).unwrap();
+    println!("{}", region.name());
+
+    region.start
+}
+
+fn kernel_main(dtb: Option<Range<usize>>, reentered: bool) -> ! {
     use console::console;
 
     println!("{}", MINILOAD_LOGO);
     println!("{:^37}", bsp::board_name());
     println!();
-    println!("[ML] Requesting binary");
-    console().flush();
 
-    // Discard any spurious received characters before starting with the loader protocol.
-    console().clear_rx();
+    if reentered {
+        println!("[ML] Payload returned to the loader");
+    }
+    let (_, privilege_level) = cpu::current_privilege_level();
+    println!("[ML] Current privilege level: {}", privilege_level);
+    println!(
+        "[ML] Architectural timer resolution: {} ns",
+        time::time_manager().resolution().as_nanos()
+    );
+    println!("[ML] Exception handling state:");
+    exception::asynchronous::print_state();
+    println!(
+        "[ML] Re-entry vector at {:#x}, magic {:#x} in x0",
+        bsp::memory::reentry_vector(),
+        cpu::boot::REENTRY_MAGIC
+    );
+
+    // The firmware lists in the device tree what it leaves to the ARM cores, which depends on
+    // how much it gave the GPU.
+    let ram_end = dtb.as_ref().and_then(|dtb| {
+        let loader = bsp::memory::loader_footprint();
+        match unsafe { loader::fdt::memory_end(dtb, loader.start) } {
+            Ok(x) => Some(x),
+            Err(x) => {
+                println!("[ML] No RAM size in the device tree ({}), assuming the default", x);
+                None
+            }
+        }
+    });
+    let mut memory = Memory {
+        low: bsp::memory::load_window(),
+        high: bsp::memory::high_window(ram_end),
+    };
+    println!("[ML] Usable RAM ends at {:#x}", memory.high.end);
+    if let Some(dtb) = &dtb {
+        println!("[ML] Device tree at {:#x}..{:#x}", dtb.start, dtb.end);
 
-    // Notify `Minipush` to send the binary.
-    for _ in 0..3 {
-        console().write_char(3 as char);
+        // Payloads must not overwrite the device tree they are handed.
+        memory.low = exclude(memory.low, dtb);
+        memory.high = exclude(memory.high, dtb);
     }
 
-    // Read the binary's size.
-    let mut size: u32 = u32::from(console().read_char() as u8);
-    size |= u32::from(console().read_char() as u8) << 8;
-    size |= u32::from(console().read_char() as u8) << 16;
-    size |= u32::from(console().read_char() as u8) << 24;
+    let mut handoff = Handoff {
+        dtb,
+        // The only reference to the buffer: `kernel_main` runs once per boot.
+        cmdline: unsafe { &mut *core::ptr::addr_of_mut!(CMDLINE) },
+        cmdline_len: None,
+        linux: None,
+    };
+    let mut line = DEFAULT_LINE;
+    let mut announce = true;
+    let loaded = loop {
+        if announce {
+            println!("[ML] Requesting binary");
+        }
+        console().flush();
 
-    // Trust it's not too big.
-    console().write_char('O');
-    console().write_char('K');
+        // Discard any spurious received characters before starting with the loader protocol.
+        console().clear_rx();
 
-    let kernel_addr: *mut u8 = bsp::memory::board_default_load_addr() as *mut u8;
-    unsafe {
-        // Read the kernel byte by byte.
-        for i in 0..size {
-            core::ptr::write_volatile(kernel_addr.offset(i as isize), console().read_char() as u8)
+        // Notify `Minipush` to send the binary. XMODEM senders are asked by `receive_start`.
+        for _ in 0..3 {
+            console().write_char(3 as char);
         }
+
+        let result = match loader::receive_start() {
+            Ok(loader::Start::Minipush) => receive_minipush(&memory, &mut handoff),
+            Ok(loader::Start::Xmodem(x)) => receive_xmodem(x, &memory, &mut handoff),
+            Ok(loader::Start::Baud) => {
+                // The host expects the binary to be requested again, at the agreed rate.
+                line = loader::baud::negotiate(line, switch_line);
+                continue;
+            }
+            Ok(loader::Start::Monitor) => match loader::monitor::serve() {
+                Ok(Some(addr)) => {
+                    // Whatever a failed transfer left behind is not meant for this code.
+                    handoff.cmdline_len = None;
+                    handoff.linux = None;
+                    Ok(Loaded::Image(addr))
+                }
+                Ok(None) => {
+                    // The host waits for the next request, which need not show on its console.
+                    announce = false;
+                    continue;
+                }
+                Err(x) => Err(x),
+            },
+            Err(x) => Err(x),
+        };
+
+        // Keep asking quietly while nobody answers.
+        announce = !matches!(result, Err(loader::Error::Idle));
+
+        match result {
+            Ok(x) => break x,
+            Err(loader::Error::Idle) => {}
+            Err(x) => {
+                loader::abort();
+                println!("[ML] Transfer failed: {}", x);
+                print_line_errors();
+            }
+        }
+    };
+
+    // The payload expects the line at the default setting. Give the host a moment to switch back
+    // after the final ACK before talking again.
+    if line != DEFAULT_LINE {
+        let _ = switch_line(DEFAULT_LINE);
+        time::time_manager().spin_for(BAUD_RESTORE_DELAY);
     }
 
+    if let Some(len) = handoff.cmdline_len {
+        let cmdline = core::str::from_utf8(&handoff.cmdline[..len]).unwrap_or("<not UTF-8>");
+        println!("[ML] Command line: {}", cmdline);
+    }
+    if let (Some(kernel), Some(dtb)) = (&handoff.linux, &handoff.dtb) {
+        println!(
+            "[ML] Linux at {:#x}..{:#x}, device tree at {:#x}",
+            kernel.start, kernel.end, dtb.start
+        );
+    }
+    let entry = match loaded {
+        Loaded::Image(x) => x,
+        Loaded::Manifest(manifest) => boot_menu(&manifest),
+    };
     println!("[ML] Loaded! Executing the payload now\n");
     console().flush();
 
-    // Use black magic to create a function pointer.
-    let kernel: fn() -> ! = unsafe { core::mem::transmute(kernel_addr) };
+    // The payload brings its own vectors and drivers, so nothing may be left to interrupt it.
+    exception::asynchronous::local_irq_mask();
+    exception::asynchronous::irq_manager().disable_all();
+
+    // Payloads start with the MMU and caches off, and the payload must be in memory, not just in
+    // the data cache.
+    unsafe { memory::mmu::disable_mmu_and_caching() };
+
+    // Use black magic to create a function pointer. The C calling convention puts the arguments
+    // in x0..x3.
+    let kernel: extern "C" fn(u64, u64, u64, u64) -> ! = unsafe { core::mem::transmute(entry) };
+    let [x0, x1, x2, x3] = handoff.registers();
 
     // Jump to loaded kernel!
-    kernel()
+    kernel(x0, x1, x2, x3)
 }
\ No newline at end of file
@@ -0,0 +1,12 @@
+//! The memory management unit.
+//!
+//! The loader maps the lower 4 GiB of the physical address space 1:1: RAM as normal, cacheable
+//! memory and everything from `bsp::memory::device_memory_start` up as device memory. It turns
+//! the MMU on for the data cache, which the exclusive loads and stores behind `SpinLock` need.
+//! With the MMU off, all memory is device memory, and the Raspberry Pi has no global exclusive
+//! monitor that would let exclusive stores to it succeed.
+
+#[path = "../_arch/aarch64/memory/mmu.rs"]
+mod aarch_mmu;
+
+pub use aarch_mmu::{disable_mmu_and_caching, enable_mmu_and_caching};
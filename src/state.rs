@@ -0,0 +1,44 @@
+//! The phase the loader is in.
+//!
+//! Everything registered through a `ReadWriteExclusive` (console, IRQ manager, drivers, IRQ
+//! handlers) is written while the loader initialises and only read afterwards.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Tracks whether the loader is still initialising.
+///
+/// Only plain loads and stores, so it also works before `kernel_init` turns the MMU on, which
+/// read-modify-write atomics need.
+pub struct StateManager {
+    init_done: AtomicBool,
+}
+
+/// In `.bss`, so a payload that comes back starts the loader in the init phase again.
+static STATE_MANAGER: StateManager = StateManager::new();
+
+/// Return a reference to the global StateManager.
+pub fn state_manager() -> &'static StateManager {
+    &STATE_MANAGER
+}
+
+impl StateManager {
+    const fn new() -> Self {
+        Self {
+            init_done: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the loader is still initialising.
+    pub fn is_init(&self) -> bool {
+        !self.init_done.load(Ordering::Acquire)
+    }
+
+    /// Leave the init phase. From here on, nothing may be registered any more.
+    pub fn transition_to_single_core_main(&self) {
+        if !self.is_init() {
+            panic!("Init phase already left");
+        }
+
+        self.init_done.store(true, Ordering::Release);
+    }
+}
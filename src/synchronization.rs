@@ -1,4 +1,7 @@
 use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::exception::asynchronous::{exec_with_irq_masked, is_local_irq_masked};
+use crate::state;
 
 pub mod interface {
 
@@ -7,33 +10,145 @@ pub mod interface {
 
         fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R;
     }
+
+    /// A lock with exclusive writers and any number of concurrent readers.
+    pub trait ReadWriteEx {
+        type Data;
+
+        fn write<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R;
+
+        fn read<'a, R>(&'a self, f: impl FnOnce(&'a Self::Data) -> R) -> R;
+    }
 }
 
-pub struct NullLock<T>
+/// A lock for data shared with IRQ handlers on a single core: IRQs are masked while it is held,
+/// so no handler can run in the middle of the critical section. Gives no protection against other
+/// cores.
+pub struct IRQSafeNullLock<T>
     where
         T: ?Sized
 {
     data: UnsafeCell<T>,
 }
 
+/// A ticket spin lock. Cores get the lock in the order they asked for it.
+///
+/// IRQs are masked while the lock is held, so an IRQ handler cannot deadlock on a lock its core
+/// already holds.
+///
+/// The tickets are exclusive loads and stores, which only succeed with the MMU and data cache
+/// on, see `memory::mmu`. The lock must not be used before `kernel_init` turns them on or after
+/// they are turned off for the payload.
+pub struct SpinLock<T>
+    where
+        T: ?Sized
+{
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+    data: UnsafeCell<T>,
+}
 
-unsafe impl<T> Send for NullLock<T> where T: ?Sized + Send {}
+/// A lock for data written during init and read afterwards, like the registered drivers or the
+/// console.
+///
+/// Writers must run during the init phase and with IRQs masked, which `write` asserts. Readers
+/// take no lock at all. It is therefore only sound on a single core, and as long as nothing writes
+/// while a reader is active, e.g. from within `read`.
+pub struct ReadWriteExclusive<T>
+    where
+        T: ?Sized
+{
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for IRQSafeNullLock<T> where T: ?Sized + Send {}
+
+unsafe impl<T> Sync for IRQSafeNullLock<T> where T: ?Sized + Send {}
+
+unsafe impl<T> Send for SpinLock<T> where T: ?Sized + Send {}
+
+unsafe impl<T> Sync for SpinLock<T> where T: ?Sized + Send {}
+
+unsafe impl<T> Send for ReadWriteExclusive<T> where T: ?Sized + Send {}
+
+unsafe impl<T> Sync for ReadWriteExclusive<T> where T: ?Sized + Send {}
+
+impl<T> IRQSafeNullLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            data: UnsafeCell::new(data)
+        }
+    }
+}
 
-unsafe impl<T> Sync for NullLock<T> where T: ?Sized + Send {}
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            data: UnsafeCell::new(data)
+        }
+    }
+}
 
-impl<T> NullLock<T> {
+impl<T> ReadWriteExclusive<T> {
     pub const fn new(data: T) -> Self {
-        NullLock {
+        Self {
             data: UnsafeCell::new(data)
         }
     }
 }
 
-impl<T> interface::Mutex for NullLock<T> {
+impl<T> interface::Mutex for IRQSafeNullLock<T> {
     type Data = T;
 
     fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
         let data = unsafe { &mut *self.data.get() };
+
+        exec_with_irq_masked(|| f(data))
+    }
+}
+
+impl<T> interface::Mutex for SpinLock<T> {
+    type Data = T;
+
+    fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
+        exec_with_irq_masked(|| {
+            let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+            while self.now_serving.load(Ordering::Acquire) != ticket {
+                core::hint::spin_loop();
+            }
+
+            let data = unsafe { &mut *self.data.get() };
+            let ret = f(data);
+
+            self.now_serving.store(ticket.wrapping_add(1), Ordering::Release);
+            ret
+        })
+    }
+}
+
+impl<T> interface::ReadWriteEx for ReadWriteExclusive<T> {
+    type Data = T;
+
+    fn write<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
+        assert!(
+            state::state_manager().is_init(),
+            "ReadWriteExclusive::write called after the init phase"
+        );
+        assert!(
+            is_local_irq_masked(),
+            "ReadWriteExclusive::write called with IRQs unmasked"
+        );
+
+        let data = unsafe { &mut *self.data.get() };
+
+        f(data)
+    }
+
+    fn read<'a, R>(&'a self, f: impl FnOnce(&'a Self::Data) -> R) -> R {
+        let data = unsafe { &*self.data.get() };
+
         f(data)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,36 @@
+#[cfg(not(feature = "system-timer"))]
+#[path = "./_arch/aarch64/time.rs"]
+mod aarch_time;
+
+pub mod interface {
+    use core::time::Duration;
+
+    pub trait TimeManager {
+        /// The smallest step the clock advances by.
+        fn resolution(&self) -> Duration;
+
+        /// Time since the clock started.
+        fn uptime(&self) -> Duration;
+
+        /// Busy-wait for `duration`.
+        fn spin_for(&self, duration: Duration) {
+            let deadline = self.uptime() + duration;
+            while self.uptime() < deadline {}
+        }
+    }
+}
+
+/// The clock everything is timed by: the architectural timer. It runs from reset without a
+/// driver, and at 19.2 MHz (Raspberry Pi 3) or 54 MHz (Raspberry Pi 4) it is finer than the BCM
+/// system timer's 1 MHz.
+#[cfg(not(feature = "system-timer"))]
+pub fn time_manager() -> &'static dyn interface::TimeManager {
+    &aarch_time::GENERIC_TIMER
+}
+
+/// The clock everything is timed by: the BCM system timer, the counter the VPU firmware uses as
+/// well.
+#[cfg(feature = "system-timer")]
+pub fn time_manager() -> &'static dyn interface::TimeManager {
+    crate::bsp::time::system_timer()
+}